@@ -1,50 +1,122 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use header::{CartridgeError, Header};
+use mbc::Mbc;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub mod header;
+pub mod mbc;
+pub mod rtc;
 
 /// The Cartridge's ROM gets stored in memory banks of 16Kb sizes each. The
 /// system contains two 16Kb banks so any game of 32Kb or smaller doesn't rely
 /// on banking. RAM gets banked in 8Kb chuncks and the system has a RAM size of
 /// 8Kb onboard already.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Cartridge {
     // memory bank controller
-    pub mbc: Vec<u8>,
+    pub mbc: Box<dyn Mbc>,
     pub rom: Vec<u8>,
     pub ram: Vec<u8>,
-    pub title: String,
-    pub model: Model,
-    pub rom_size: u32,
-    pub ram_size: Option<u32>,
+    pub header: Header,
+    // `.sav` file next to the ROM, only battery backed cartridges have one
+    pub save_path: Option<PathBuf>,
+    // RAM was written since the last flush
+    dirty: bool,
 }
 
 impl Cartridge {
-    pub fn new(rom_path: &PathBuf) -> Cartridge {
-        let mut rom: Vec<u8> = Vec::new();
-        File::open(rom_path)
-            .and_then(|mut f| f.read_to_end(&mut rom))
-            .expect("GB Rom file did not open");
-        let title: String = std::str::from_utf8(&rom[0x134..0x13E]).unwrap().to_owned();
-        let model: Model = rom[0x147].into();
-        let rom_size: u32 = 1 << (15 + rom[0x148]);
-        let ram_size: Option<u32> = match rom[0x149] {
-            0 => Some(0),
-            1 => None,
-            2 => Some(1 << 13),
-            3 => Some(1 << 15),
-            4 => Some(1 << 17),
-            5 => Some(1 << 16),
-            _ => None,
-        };
+    /// Load a ROM file along with the `.sav` file at `save_path` if it has
+    /// battery backed memory.
+    pub fn new(rom_path: &Path, save_path: &Path) -> Result<Cartridge, CartridgeError> {
+        let mut cartridge = Cartridge::from_rom(fs::read(rom_path)?)?;
+        if cartridge.header.model.battery()
+            && (!cartridge.ram.is_empty() || cartridge.mbc.rtc().is_some())
+        {
+            cartridge.save_path = Some(save_path.to_path_buf());
+            cartridge.load_save();
+        }
+        Ok(cartridge)
+    }
 
-        Cartridge {
+    /// A cartridge for a ROM that isn't backed by a file, it never gets saved.
+    pub fn from_rom(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = Header::parse(&rom)?;
+        let ram = match header.model {
+            // MBC2 has 512 half bytes built in regardless of the header
+            Model::MBC2 | Model::MBC2BATTERY => vec![0; 512],
+            _ => vec![0; header.ram_size.unwrap_or(0) as usize],
+        };
+        Ok(Cartridge {
+            mbc: mbc::new(header.model),
             rom,
-            title,
-            model,
-            rom_size,
-            ram_size,
-            ram: Vec::new(),
-            mbc: Vec::new(),
+            ram,
+            header,
+            save_path: None,
+            dirty: false,
+        })
+    }
+
+    /// Identifies the ROM by its header and global checksums along with the
+    /// cartridge type, save states only load into the ROM they came from.
+    pub fn id(&self) -> u32 {
+        u32::from_le_bytes([
+            self.rom[0x147],
+            self.rom[0x14D],
+            self.rom[0x14E],
+            self.rom[0x14F],
+        ])
+    }
+
+    /// Fill RAM from the `.sav` file if there is one. Saves are the raw
+    /// contents of RAM, followed by the real time clock's trailer for MBC3
+    /// timer cartridges. A file of the wrong size is used as far as it goes.
+    fn load_save(&mut self) {
+        let Some(data) = self.save_path.as_ref().and_then(|p| fs::read(p).ok()) else {
+            return;
+        };
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        let trailer = data.get(len..len + rtc::TRAILER_SIZE);
+        if let (Some(rtc), Some(trailer)) = (self.mbc.rtc(), trailer) {
+            rtc.load_trailer(trailer.try_into().unwrap());
+        }
+    }
+
+    /// Write RAM to the `.sav` file if it changed since the last flush.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mbc.rtc() {
+            data.extend(rtc.save_trailer());
+        }
+        fs::write(path, data)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Read from the cartridge's ROM (0x0000-0x7FFF) or RAM (0xA000-0xBFFF).
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.read_rom(&self.rom, addr),
+            _ => self.mbc.read_ram(&self.ram, addr),
+        }
+    }
+
+    /// Writes to the ROM area program the memory bank controller.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.write_rom(addr, val),
+            _ => {
+                self.mbc.write_ram(&mut self.ram, addr, val);
+                self.dirty = true;
+            }
         }
     }
 }
@@ -83,6 +155,26 @@ pub enum Model {
     NA = 0xAA,
 }
 
+impl Model {
+    /// Whether a battery keeps the cartridge's RAM alive without power.
+    pub fn battery(&self) -> bool {
+        matches!(
+            self,
+            Model::MBC1RAMBATTERY
+                | Model::MBC2BATTERY
+                | Model::ROMRAMBATTERY
+                | Model::MMM01RAMBATTERY
+                | Model::MBC3TIMERBATTERY
+                | Model::MBC3TIMERRAMBATTERY
+                | Model::MBC3RAMBATTERY
+                | Model::MBC5RAMBATTERY
+                | Model::MBC5RUMBLERAMBATTERY
+                | Model::MBC7SENSORRUMBLERAMBATTERY
+                | Model::HuC1RAMBATTERY
+        )
+    }
+}
+
 impl From<u8> for Model {
     fn from(n: u8) -> Self {
         match n {
@@ -119,6 +211,22 @@ impl From<u8> for Model {
     }
 }
 
+/// Only what the game can change is saved, the ROM itself has to be loaded
+/// already.
+impl Snapshot for Cartridge {
+    fn save(&self, w: &mut Writer) {
+        w.vec(&self.ram);
+        self.mbc.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.ram, "cartridge RAM size")?;
+        // make sure the battery save catches up with the restored RAM
+        self.dirty = true;
+        self.mbc.load(r)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::BootParameters;
@@ -128,15 +236,60 @@ mod tests {
     fn setup() -> Cartridge {
         let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("rom_tests/blarggs-test-roms/cpu_instrs/cpu_instrs.gb");
-        let bp = BootParameters::new(p.to_str());
-        Cartridge::new(&bp.rom_path)
+        let bp = BootParameters::new(&p);
+        Cartridge::new(&bp.rom_path, &bp.save_path("sav")).unwrap()
     }
 
     #[test]
     fn test_initialization() {
         let c = setup();
-        assert_eq!(c.title.as_str(), "CPU_INSTRS");
-        assert_eq!(c.ram_size, Some(0));
-        assert_eq!(c.rom_size, 1 << 16);
+        assert_eq!(c.header.title.as_str(), "CPU_INSTRS");
+        assert_eq!(c.header.ram_size, Some(0));
+        assert_eq!(c.header.rom_size, 1 << 16);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("missing.gb");
+        assert!(matches!(Cartridge::new(&p, &p), Err(CartridgeError::Io(_))));
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut c = setup();
+        assert_eq!(c.read(0x4000), c.rom[0x4000]);
+        c.write(0x2000, 0x00);
+        assert_eq!(c.read(0x4000), c.rom[0x4000]);
+        c.write(0x2000, 0x02);
+        assert_eq!(c.read(0x4000), c.rom[0x4000]);
+        // writes never modify the rom
+        c.write(0x0100, 0xAA);
+        assert_eq!(c.read(0x0100), c.rom[0x0100]);
+    }
+
+    #[test]
+    fn test_battery_save() {
+        let dir = std::env::temp_dir().join(format!("they-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("battery.gb");
+        let save_path = dir.join("battery.sav");
+        // MBC1 with 8Kb of battery backed RAM
+        let mut rom = header::tests::rom();
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        header::tests::fix_checksum(&mut rom);
+        fs::write(&rom_path, &rom).unwrap();
+
+        let mut c = Cartridge::new(&rom_path, &save_path).unwrap();
+        assert_eq!(c.save_path, Some(save_path.clone()));
+        c.write(0x0000, 0x0A);
+        c.write(0xA123, 0x42);
+        c.flush().unwrap();
+        assert_eq!(fs::read(&save_path).unwrap().len(), 0x2000);
+
+        let mut c = Cartridge::new(&rom_path, &save_path).unwrap();
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA123), 0x42);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
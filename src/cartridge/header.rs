@@ -0,0 +1,293 @@
+use super::Model;
+use std::fmt;
+use std::io;
+
+// the header ends with the global checksum at 0x14E-0x14F
+const HEADER_END: usize = 0x150;
+
+/// The boot ROM compares this bitmap at 0x104-0x133 with its own copy and
+/// locks up if they differ.
+pub const LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the file is too small to hold a header, with its actual size
+    Truncated(usize),
+    // the logo at 0x104-0x133 doesn't match
+    Logo,
+    HeaderChecksum { expected: u8, actual: u8 },
+    RomSize(u8),
+    RamSize(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{e}"),
+            CartridgeError::Truncated(len) => {
+                write!(f, "ROM is {len} bytes, too small to hold a header")
+            }
+            CartridgeError::Logo => write!(f, "ROM doesn't contain the Nintendo logo"),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum is {actual:#04x} but the header sums to {expected:#04x}"
+            ),
+            CartridgeError::RomSize(v) => write!(f, "unknown ROM size {v:#04x}"),
+            CartridgeError::RamSize(v) => write!(f, "unknown RAM size {v:#04x}"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+/// Byte 0x143, which used to be the last character of the title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbFlag {
+    Dmg,
+    // works on both, with CGB enhancements
+    Supported,
+    Only,
+}
+
+/// Who published the game. Older cartridges store a single byte at 0x14B,
+/// newer ones set it to 0x33 and store two ASCII characters at 0x144-0x145.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// Cartridge header at 0x100-0x14F.
+///
+///   * 0x134-0x143 - title, upper case ASCII padded with zeros. Later
+///     cartridges shortened it to make room for the manufacturer code
+///     (0x13F-0x142) and the CGB flag (0x143)
+///   * 0x144-0x145 - new licensee code
+///   * 0x146 - SGB flag, 0x03 enables SGB functions
+///   * 0x147 - cartridge type, see `Model`
+///   * 0x148 - ROM size, 32Kb << n
+///   * 0x149 - RAM size
+///   * 0x14A - destination code
+///   * 0x14B - old licensee code
+///   * 0x14C - version number
+///   * 0x14D - header checksum over 0x134-0x14C
+///   * 0x14E-0x14F - global checksum, big endian sum of every other byte
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbFlag,
+    pub licensee: Licensee,
+    pub sgb: bool,
+    pub model: Model,
+    pub rom_size: u32,
+    // None when the header doesn't say, MBC2 has its RAM built in
+    pub ram_size: Option<u32>,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    // the boot ROM ignores the global checksum and so do plenty of ROMs
+    pub global_checksum_valid: bool,
+}
+
+impl Header {
+    /// Parse and verify the header the way the boot ROM would, checking the
+    /// logo and the header checksum.
+    pub fn parse(rom: &[u8]) -> Result<Header, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated(rom.len()));
+        }
+        if rom[0x104..0x134] != LOGO {
+            return Err(CartridgeError::Logo);
+        }
+        let expected = checksum(rom);
+        if expected != rom[0x14D] {
+            return Err(CartridgeError::HeaderChecksum {
+                expected,
+                actual: rom[0x14D],
+            });
+        }
+
+        let cgb = match rom[0x143] {
+            0x80 => CgbFlag::Supported,
+            0xC0 => CgbFlag::Only,
+            _ => CgbFlag::Dmg,
+        };
+        // a manufacturer code only fits when the title was shortened for the
+        // CGB flag, and is told apart from a long title by being 4 characters
+        // following padding
+        let code = &rom[0x13F..0x143];
+        let manufacturer =
+            (cgb != CgbFlag::Dmg && rom[0x13E] == 0 && code.iter().all(u8::is_ascii_alphanumeric))
+                .then(|| text(code));
+        let title_end = match (cgb, &manufacturer) {
+            (_, Some(_)) => 0x13F,
+            (CgbFlag::Dmg, None) => 0x144,
+            _ => 0x143,
+        };
+        let licensee = match rom[0x14B] {
+            0x33 => Licensee::New(text(&rom[0x144..0x146])),
+            old => Licensee::Old(old),
+        };
+        let rom_size = match rom[0x148] {
+            n @ 0..=8 => 0x8000 << n,
+            n => return Err(CartridgeError::RomSize(n)),
+        };
+        let ram_size = match rom[0x149] {
+            0 => Some(0),
+            1 => None,
+            2 => Some(1 << 13),
+            3 => Some(1 << 15),
+            4 => Some(1 << 17),
+            5 => Some(1 << 16),
+            n => return Err(CartridgeError::RamSize(n)),
+        };
+        let global_checksum = u16::from_be_bytes([rom[0x14E], rom[0x14F]]);
+        let sum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+
+        Ok(Header {
+            title: text(&rom[0x134..title_end]),
+            manufacturer,
+            cgb,
+            licensee,
+            sgb: rom[0x146] == 0x03,
+            model: rom[0x147].into(),
+            rom_size,
+            ram_size,
+            destination: match rom[0x14A] {
+                0 => Destination::Japan,
+                _ => Destination::Overseas,
+            },
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum,
+            global_checksum_valid: sum == global_checksum,
+        })
+    }
+}
+
+/// What the header checksum at 0x14D has to be.
+pub(crate) fn checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// ASCII text up to the first zero, anything else is replaced.
+fn text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| if b.is_ascii() { b as char } else { '\u{FFFD}' })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Smallest ROM with a valid header, patch it with `fix_checksum` after
+    /// changing any of 0x134-0x14C.
+    pub(crate) fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&LOGO);
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    pub(crate) fn fix_checksum(rom: &mut [u8]) {
+        rom[0x14D] = checksum(rom);
+    }
+
+    #[test]
+    fn title_test() {
+        let mut rom = rom();
+        rom[0x134..0x144].copy_from_slice(b"SIXTEEN CHAR NAM");
+        fix_checksum(&mut rom);
+        let h = Header::parse(&rom).unwrap();
+        assert_eq!(h.title, "SIXTEEN CHAR NAM");
+        assert_eq!(h.cgb, CgbFlag::Dmg);
+        assert_eq!(h.manufacturer, None);
+
+        rom[0x134..0x144].copy_from_slice(b"POKEMON\0\0\0\0AAUE\xC0");
+        fix_checksum(&mut rom);
+        let h = Header::parse(&rom).unwrap();
+        assert_eq!(h.title, "POKEMON");
+        assert_eq!(h.manufacturer.as_deref(), Some("AAUE"));
+        assert_eq!(h.cgb, CgbFlag::Only);
+
+        // not UTF-8
+        rom[0x134] = 0xFF;
+        fix_checksum(&mut rom);
+        assert_eq!(Header::parse(&rom).unwrap().title, "\u{FFFD}OKEMON");
+    }
+
+    #[test]
+    fn metadata_test() {
+        let mut rom = rom();
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x146] = 0x03;
+        rom[0x147] = 0x1B;
+        rom[0x148] = 0x05;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14C] = 0x02;
+        fix_checksum(&mut rom);
+        let h = Header::parse(&rom).unwrap();
+        assert_eq!(h.licensee, Licensee::New("01".to_owned()));
+        assert!(h.sgb);
+        assert!(matches!(h.model, Model::MBC5RAMBATTERY));
+        assert_eq!(h.rom_size, 1 << 20);
+        assert_eq!(h.ram_size, Some(1 << 15));
+        assert_eq!(h.destination, Destination::Overseas);
+        assert_eq!(h.version, 2);
+        assert!(!h.global_checksum_valid);
+    }
+
+    #[test]
+    fn invalid_test() {
+        assert!(matches!(
+            Header::parse(&[0; 0x100]),
+            Err(CartridgeError::Truncated(0x100))
+        ));
+        let mut bad = rom();
+        bad[0x14D] ^= 1;
+        assert!(matches!(
+            Header::parse(&bad),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+        let mut bad = rom();
+        bad[0x104] = 0;
+        assert!(matches!(Header::parse(&bad), Err(CartridgeError::Logo)));
+        let mut bad = rom();
+        bad[0x148] = 0x52;
+        fix_checksum(&mut bad);
+        assert!(matches!(
+            Header::parse(&bad),
+            Err(CartridgeError::RomSize(0x52))
+        ));
+    }
+}
@@ -0,0 +1,538 @@
+use super::Model;
+use super::rtc::{Rtc, SystemClock};
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use std::fmt;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// Memory bank controllers live on the cartridge and decide which banks of
+/// ROM and RAM the CPU sees. The ROM can't be written to, instead writes to
+/// 0x0000-0x7FFF program the controller's registers.
+///
+///   * 0x0000-0x3FFF - ROM bank 0 (usually fixed)
+///   * 0x4000-0x7FFF - switchable ROM bank
+///   * 0xA000-0xBFFF - switchable external RAM bank
+pub trait Mbc: fmt::Debug + Snapshot {
+    /// Read from the ROM area 0x0000-0x7FFF.
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8;
+
+    /// Read from the external RAM area 0xA000-0xBFFF.
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8;
+
+    /// Write to the controller's registers in 0x0000-0x7FFF.
+    fn write_rom(&mut self, addr: u16, val: u8);
+
+    /// Write to the external RAM area 0xA000-0xBFFF.
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8);
+
+    /// The real time clock, for controllers that have one.
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+}
+
+/// Pick the controller matching the cartridge header.
+pub fn new(model: Model) -> Box<dyn Mbc> {
+    match model {
+        Model::MBC1 | Model::MBC1RAM | Model::MBC1RAMBATTERY => Box::new(Mbc1::new()),
+        Model::MBC2 | Model::MBC2BATTERY => Box::new(Mbc2::new()),
+        Model::MBC3 | Model::MBC3RAM | Model::MBC3RAMBATTERY => Box::new(Mbc3::new()),
+        Model::MBC3TIMERBATTERY | Model::MBC3TIMERRAMBATTERY => {
+            Box::new(Mbc3::with_rtc(Rtc::new(Box::new(SystemClock))))
+        }
+        Model::MBC5
+        | Model::MBC5RAM
+        | Model::MBC5RAMBATTERY
+        | Model::MBC5RUMBLE
+        | Model::MBC5RUMBLERAM
+        | Model::MBC5RUMBLERAMBATTERY => Box::new(Mbc5::new()),
+        // unsupported controllers at least get their first 32Kb mapped
+        _ => Box::new(NoMbc),
+    }
+}
+
+/// Read a byte out of a bank, the bank number wraps around the available
+/// banks and anything past the end of the data reads as open bus.
+fn banked_read(data: &[u8], bank: usize, bank_size: usize, addr: u16) -> u8 {
+    let banks = (data.len() / bank_size).max(1);
+    let offset = (bank % banks) * bank_size + (addr as usize % bank_size);
+    data.get(offset).copied().unwrap_or(0xFF)
+}
+
+fn banked_write(data: &mut [u8], bank: usize, bank_size: usize, addr: u16, val: u8) {
+    let banks = (data.len() / bank_size).max(1);
+    let offset = (bank % banks) * bank_size + (addr as usize % bank_size);
+    if let Some(b) = data.get_mut(offset) {
+        *b = val;
+    }
+}
+
+/// 32Kb games that map the ROM directly, optionally with up to 8Kb of RAM.
+#[derive(Debug)]
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        rom.get(addr as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        banked_read(ram, 0, RAM_BANK_SIZE, addr)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        banked_write(ram, 0, RAM_BANK_SIZE, addr, val);
+    }
+}
+
+/// MBC1 supports up to 2Mb of ROM and 32Kb of RAM.
+///
+///   * 0x0000-0x1FFF - RAM enable, 0x0A in the lower nibble enables
+///   * 0x2000-0x3FFF - lower 5 bits of the ROM bank, 0 is treated as 1
+///   * 0x4000-0x5FFF - RAM bank or upper 2 bits of the ROM bank
+///   * 0x6000-0x7FFF - banking mode, 1 applies the upper bits to bank 0 and RAM
+#[derive(Debug)]
+pub struct Mbc1 {
+    pub ram_enabled: bool,
+    pub bank_low: u8,
+    pub bank_high: u8,
+    pub mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new() -> Mbc1 {
+        Mbc1 {
+            ram_enabled: false,
+            bank_low: 1,
+            bank_high: 0,
+            mode: false,
+        }
+    }
+}
+
+impl Default for Mbc1 {
+    fn default() -> Self {
+        Mbc1::new()
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3FFF if self.mode => (self.bank_high as usize) << 5,
+            0x0000..=0x3FFF => 0,
+            _ => (self.bank_high as usize) << 5 | self.bank_low as usize,
+        };
+        banked_read(rom, bank, ROM_BANK_SIZE, addr)
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        let bank = if self.mode { self.bank_high } else { 0 };
+        banked_read(ram, bank as usize, RAM_BANK_SIZE, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank_low = (val & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank_high = val & 0b11,
+            _ => self.mode = val & 1 == 1,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        if self.ram_enabled {
+            let bank = if self.mode { self.bank_high } else { 0 };
+            banked_write(ram, bank as usize, RAM_BANK_SIZE, addr, val);
+        }
+    }
+}
+
+/// MBC2 supports up to 256Kb of ROM and has 512 x 4 bits of RAM built in,
+/// which gets echoed through the whole external RAM area.
+///
+///   * 0x0000-0x3FFF - bit 8 of the address clear is RAM enable, set is the
+///     ROM bank (lower 4 bits, 0 is treated as 1)
+#[derive(Debug)]
+pub struct Mbc2 {
+    pub ram_enabled: bool,
+    pub bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new() -> Mbc2 {
+        Mbc2 {
+            ram_enabled: false,
+            bank: 1,
+        }
+    }
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Mbc2::new()
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.bank };
+        banked_read(rom, bank as usize, ROM_BANK_SIZE, addr)
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // only the lower nibble is wired up
+        ram.get(addr as usize & 0x1FF).copied().unwrap_or(0xFF) | 0xF0
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x3FFF if addr & 0x100 == 0 => self.ram_enabled = val & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.bank = (val & 0x0F).max(1),
+            _ => (),
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(b) = ram.get_mut(addr as usize & 0x1FF) {
+            *b = val & 0x0F;
+        }
+    }
+}
+
+/// MBC3 supports up to 2Mb of ROM, 32Kb of RAM and a real time clock.
+///
+///   * 0x0000-0x1FFF - RAM and RTC enable, 0x0A in the lower nibble enables
+///   * 0x2000-0x3FFF - ROM bank (7 bits), 0 is treated as 1
+///   * 0x4000-0x5FFF - RAM bank 0x00-0x03 or RTC register 0x08-0x0C
+///   * 0x6000-0x7FFF - latch clock data
+#[derive(Debug)]
+pub struct Mbc3 {
+    pub ram_enabled: bool,
+    pub rom_bank: u8,
+    pub ram_bank: u8,
+    pub rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new() -> Mbc3 {
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: None,
+        }
+    }
+
+    pub fn with_rtc(rtc: Rtc) -> Mbc3 {
+        Mbc3 {
+            rtc: Some(rtc),
+            ..Mbc3::new()
+        }
+    }
+}
+
+impl Default for Mbc3 {
+    fn default() -> Self {
+        Mbc3::new()
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        banked_read(rom, bank as usize, ROM_BANK_SIZE, addr)
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        match self.ram_bank {
+            0x00..=0x03 if self.ram_enabled => {
+                banked_read(ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+            }
+            0x08..=0x0C if self.ram_enabled => match &self.rtc {
+                Some(rtc) => rtc.read(self.ram_bank),
+                None => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (val & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.ram_bank, &mut self.rtc) {
+            (0x00..=0x03, _) => banked_write(ram, self.ram_bank as usize, RAM_BANK_SIZE, addr, val),
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_bank, val),
+            _ => (),
+        }
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+/// MBC5 supports up to 8Mb of ROM and 128Kb of RAM, unlike the other
+/// controllers ROM bank 0 can be mapped into 0x4000-0x7FFF.
+///
+///   * 0x0000-0x1FFF - RAM enable, 0x0A in the lower nibble enables
+///   * 0x2000-0x2FFF - lower 8 bits of the ROM bank
+///   * 0x3000-0x3FFF - 9th bit of the ROM bank
+///   * 0x4000-0x5FFF - RAM bank 0x00-0x0F
+#[derive(Debug)]
+pub struct Mbc5 {
+    pub ram_enabled: bool,
+    pub rom_bank: u16,
+    pub ram_bank: u8,
+}
+
+impl Mbc5 {
+    pub fn new() -> Mbc5 {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl Default for Mbc5 {
+    fn default() -> Self {
+        Mbc5::new()
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        banked_read(rom, bank as usize, ROM_BANK_SIZE, addr)
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        banked_read(ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((val as u16 & 1) << 8),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            _ => (),
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        if self.ram_enabled {
+            banked_write(ram, self.ram_bank as usize, RAM_BANK_SIZE, addr, val);
+        }
+    }
+}
+
+impl Snapshot for NoMbc {
+    fn save(&self, _w: &mut Writer) {}
+
+    fn load(&mut self, _r: &mut Reader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+impl Snapshot for Mbc1 {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.ram_enabled);
+        w.u8(self.bank_low);
+        w.u8(self.bank_high);
+        w.bool(self.mode);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.ram_enabled = r.bool()?;
+        self.bank_low = r.u8()?;
+        self.bank_high = r.u8()?;
+        self.mode = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Mbc2 {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.ram_enabled);
+        w.u8(self.bank);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.ram_enabled = r.bool()?;
+        self.bank = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Mbc3 {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+        w.u8(self.ram_bank);
+        if let Some(rtc) = &self.rtc {
+            rtc.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+        self.ram_bank = r.u8()?;
+        match &mut self.rtc {
+            Some(rtc) => rtc.load(r),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Snapshot for Mbc5 {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.ram_enabled);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u16()? & 0x1FF;
+        self.ram_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rtc::tests::FakeClock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // every bank starts with its own bank number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for b in 0..banks {
+            rom[b * ROM_BANK_SIZE] = b as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn mbc1_rom_banking_test() {
+        let rom = rom(128);
+        let mut mbc = Mbc1::new();
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x25);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x20);
+    }
+
+    #[test]
+    fn mbc1_ram_test() {
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc1::new();
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x42);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+        mbc.write_ram(&mut ram, 0xA001, 0x24);
+        assert_eq!(ram[2 * RAM_BANK_SIZE + 1], 0x24);
+    }
+
+    #[test]
+    fn mbc2_test() {
+        let rom = rom(16);
+        let mut ram = vec![0; 512];
+        let mut mbc = Mbc2::new();
+        mbc.write_rom(0x2100, 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 3);
+        // bit 8 clear addresses RAM enable instead
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 3);
+        mbc.write_ram(&mut ram, 0xA010, 0xAB);
+        assert_eq!(mbc.read_ram(&ram, 0xA210), 0xFB);
+    }
+
+    #[test]
+    fn mbc3_test() {
+        let rom = rom(128);
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc3::new();
+        mbc.write_rom(0x2000, 0x45);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x45);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x11);
+    }
+
+    #[test]
+    fn mbc3_rtc_test() {
+        let clock = FakeClock(Rc::new(Cell::new(0)));
+        let mut ram = vec![0; RAM_BANK_SIZE];
+        let mut mbc = Mbc3::with_rtc(Rtc::new(Box::new(clock.clone())));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA000, 5);
+        clock.advance(3600);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 6);
+        // RAM is untouched by clock writes
+        assert!(ram.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn mbc5_test() {
+        let rom = rom(512);
+        let mut mbc = Mbc5::new();
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0);
+        mbc.write_rom(0x2000, 0x02);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.rom_bank, 0x102);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x02);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// size of the clock state appended to save files
+pub const TRAILER_SIZE: usize = 48;
+// the day counter is 9 bits wide
+const DAYS: u64 = 512;
+
+/// Source of wall time, in seconds since the Unix epoch.
+pub trait Clock: fmt::Debug {
+    fn now(&self) -> u64;
+}
+
+/// The host's clock.
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// Real time clock of the MBC3, it keeps counting from the cartridge battery
+/// while the Game Boy is off. Registers are selected by writing 0x08-0x0C to
+/// the RAM bank register and then accessed through 0xA000-0xBFFF:
+///
+///   * 0x08 - seconds (0-59)
+///   * 0x09 - minutes (0-59)
+///   * 0x0A - hours (0-23)
+///   * 0x0B - lower 8 bits of the day counter
+///   * 0x0C - bit 0 is the 9th bit of the day counter, bit 6 halts the clock
+///     and bit 7 is the day counter carry
+///
+/// Reads see the latched copy, which is updated by writing 0x00 and then 0x01
+/// to 0x6000-0x7FFF.
+#[derive(Debug)]
+pub struct Rtc {
+    clock: Box<dyn Clock>,
+    // seconds, minutes, hours, day low and day high
+    pub registers: [u8; 5],
+    pub latched: [u8; 5],
+    // wall time the registers were last brought up to date
+    updated: u64,
+    // last value written to the latch register
+    latch: u8,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Rtc {
+        let updated = clock.now();
+        Rtc {
+            clock,
+            registers: [0; 5],
+            latched: [0; 5],
+            updated,
+            latch: 0xFF,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.registers[4] & 0x40 != 0
+    }
+
+    /// Advance the registers by the wall time that passed since the last
+    /// update, unless the clock is halted.
+    fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.updated);
+        self.updated = now;
+        if self.halted() || elapsed == 0 {
+            return;
+        }
+        let [s, m, h, dl, dh] = self.registers;
+        let days = (dh as u64 & 1) << 8 | dl as u64;
+        let total = s as u64 + m as u64 * 60 + h as u64 * 3600 + days * 86400 + elapsed;
+        let days = total / 86400;
+        self.registers[0] = (total % 60) as u8;
+        self.registers[1] = (total / 60 % 60) as u8;
+        self.registers[2] = (total / 3600 % 24) as u8;
+        self.registers[3] = (days % DAYS) as u8;
+        self.registers[4] = (dh & 0xC0) | ((days % DAYS) >> 8) as u8;
+        if days >= DAYS {
+            self.registers[4] |= 0x80;
+        }
+    }
+
+    /// Read the latched copy of register 0x08-0x0C.
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched[reg as usize - 0x08]
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8) {
+        self.update();
+        let mask = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1][reg as usize - 0x08];
+        self.registers[reg as usize - 0x08] = val & mask;
+    }
+
+    /// Writes to 0x6000-0x7FFF, going from 0x00 to 0x01 latches the clock.
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch == 0x00 && val == 0x01 {
+            self.update();
+            self.latched = self.registers;
+        }
+        self.latch = val;
+    }
+
+    /// The trailer other emulators append to save files: the registers and
+    /// the latched registers as 32-bit little endian words followed by a
+    /// 64-bit Unix timestamp.
+    pub fn save_trailer(&mut self) -> [u8; TRAILER_SIZE] {
+        self.update();
+        let mut trailer = [0; TRAILER_SIZE];
+        let words = self.registers.iter().chain(&self.latched);
+        for (chunk, &r) in trailer.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&(r as u32).to_le_bytes());
+        }
+        trailer[40..].copy_from_slice(&self.updated.to_le_bytes());
+        trailer
+    }
+
+    /// Restore the clock from a save file trailer and catch up with the time
+    /// that passed since it was written.
+    pub fn load_trailer(&mut self, trailer: &[u8; TRAILER_SIZE]) {
+        let mut words = trailer[..40]
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()) as u8);
+        for r in self.registers.iter_mut().chain(self.latched.iter_mut()) {
+            *r = words.next().unwrap();
+        }
+        self.updated = u64::from_le_bytes(trailer[40..].try_into().unwrap());
+        self.update();
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.registers);
+        w.bytes(&self.latched);
+        w.u64(self.updated);
+        w.u8(self.latch);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes(&mut self.registers)?;
+        r.bytes(&mut self.latched)?;
+        self.updated = r.u64()?;
+        self.latch = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Clock the tests move forward by hand.
+    #[derive(Debug, Clone)]
+    pub(crate) struct FakeClock(pub Rc<Cell<u64>>);
+
+    impl FakeClock {
+        pub(crate) fn advance(&self, seconds: u64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn setup() -> (Rtc, FakeClock) {
+        let clock = FakeClock(Rc::new(Cell::new(1_000_000)));
+        (Rtc::new(Box::new(clock.clone())), clock)
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn latch_test() {
+        let (mut rtc, clock) = setup();
+        clock.advance(3 * 86400 + 2 * 3600 + 5 * 60 + 7);
+        assert_eq!(rtc.read(0x08), 0);
+        latch(&mut rtc);
+        assert_eq!(rtc.latched, [7, 5, 2, 3, 0]);
+        // the latched copy holds still until the next latch
+        clock.advance(1);
+        assert_eq!(rtc.read(0x08), 7);
+    }
+
+    #[test]
+    fn halt_carry_test() {
+        let (mut rtc, clock) = setup();
+        rtc.write(0x0C, 0x40);
+        clock.advance(100);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        clock.advance(86400);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0B), 0);
+        assert_eq!(rtc.read(0x0C), 0x80);
+    }
+
+    #[test]
+    fn trailer_test() {
+        let (mut rtc, clock) = setup();
+        rtc.write(0x09, 30);
+        let trailer = rtc.save_trailer();
+        assert_eq!(&trailer[4..8], &[30, 0, 0, 0]);
+        assert_eq!(&trailer[40..], &1_000_000u64.to_le_bytes());
+
+        // the clock kept running while the game wasn't
+        let (mut rtc, _) = setup();
+        clock.advance(60);
+        rtc.clock = Box::new(clock);
+        rtc.load_trailer(&trailer);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x09), 31);
+    }
+}
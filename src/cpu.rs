@@ -1,10 +1,11 @@
-use crate::ram::{
-    Memory,
-    MemoryRegister::{IE, IF},
-};
+use crate::Hardware;
+use crate::cartridge::header::{CgbFlag, Header};
+use crate::ram::{Memory, MemoryRegister::IF};
+use crate::state::{Reader, Snapshot, StateError, Writer};
 use instructions::operations;
 use std::fmt;
 
+pub mod disassembler;
 pub mod instructions;
 pub mod prefix_instructions;
 
@@ -42,18 +43,54 @@ pub struct Registers {
 }
 
 impl Registers {
-    /// Basic Powerup Sequence
-    ///   * may need to be adjusted based on ROM headers
+    /// Registers after the first DMG boot ROM ran, `post_boot` has the
+    /// other models.
     pub fn new() -> Registers {
+        Registers::post_boot(Hardware::Dmg0, None)
+    }
+
+    /// Cleared registers for running the boot ROM from 0x0000.
+    pub fn power_on() -> Registers {
         Registers {
-            acc: 0x1,
-            b: 0xFF,
-            c: 0x13,
+            acc: 0,
+            b: 0,
+            c: 0,
             d: 0,
-            e: 0xC1,
+            e: 0,
             flags: 0,
-            high: 0x84,
-            low: 0x03,
+            high: 0,
+            low: 0,
+            sp: 0,
+            pc: 0,
+        }
+    }
+
+    /// The registers as the model's boot ROM leaves them when it jumps to the
+    /// cartridge at 0x100. The DMG and MGB boot ROMs leave half carry and
+    /// carry set unless the header checksum is zero, and the CGB one sets
+    /// things up differently for cartridges without CGB support.
+    pub fn post_boot(model: Hardware, header: Option<&Header>) -> Registers {
+        let checksum = header.is_some_and(|h| h.header_checksum != 0);
+        let dmg_flags = ALUFlag::Z as u8 | if checksum { 0x30 } else { 0 };
+        let cgb_cartridge = header.is_some_and(|h| h.cgb != CgbFlag::Dmg);
+        let [acc, flags, b, c, d, e, high, low] = match model {
+            Hardware::Dmg0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            Hardware::Dmg => [0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Hardware::Mgb => [0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Hardware::Cgb if cgb_cartridge => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            // B holds a hash of the title for Nintendo's own cartridges, which
+            // the boot ROM used to pick a palette
+            Hardware::Cgb => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C],
+        };
+        Registers {
+            acc,
+            b,
+            c,
+            d,
+            e,
+            flags,
+            high,
+            low,
             sp: 0xFFFE,
             pc: 0x0100,
         }
@@ -80,6 +117,35 @@ impl fmt::Display for Registers {
     }
 }
 
+/// What the CPU does on one of the opcodes it doesn't have: 0xD3, 0xDB,
+/// 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC and 0xFD.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    // hang until reset like the hardware does
+    Lock,
+    // return an error from `exec`, front ends break into the debugger
+    Break,
+    // return an error from `exec`, front ends stop the emulator
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    IllegalOpcode { opcode: u8, addr: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, addr } => {
+                write!(f, "illegal opcode {opcode:02x} at {addr:04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ALUFlag {
     C = 0b00010000,
@@ -88,32 +154,61 @@ pub enum ALUFlag {
     Z = 0b10000000,
 }
 
+/// Interrupt sources ordered by priority, the lower the bit in `IF` and `IE`
+/// the higher the priority when several are pending at once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Interrupt {
     // LCD has drawn a frame
     VBlank,
     // LCD controller changed
     LCDController,
-    // serial transfer completed
-    Serial,
     // timer countdown
     Timer,
+    // serial transfer completed
+    Serial,
     // user pressed a button
     HiToLo,
-    // break out of a cpu powersaving halt
-    Halt,
+}
+
+impl Interrupt {
+    pub const PRIORITY: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LCDController,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::HiToLo,
+    ];
+
+    /// The bit representing the interrupt in the `IF` and `IE` registers.
+    pub fn mask(&self) -> u8 {
+        1 << (*self as u8)
+    }
+
+    /// The address the CPU jumps to when servicing the interrupt.
+    pub fn vector(&self) -> u16 {
+        0x40 + 8 * (*self as u16)
+    }
 }
 
 /// The CPU contains registers and the system memory because it must access it
 /// when executing instructions.
+///
+/// Interrupts are only serviced while `ime` (interrupt master enable) is set.
+/// The `ei` instruction doesn't set it right away, `ei` stays pending until
+/// the instruction after it has been executed.
 #[derive(Debug)]
 pub struct CPU {
     pub registers: Registers,
     pub memory: Memory,
     pub ime: bool,
-    pub di: bool,
     pub ei: bool,
     pub halt: bool,
+    // the byte after a `halt` gets read twice when the halt is skipped
+    pub halt_bug: bool,
     pub stop: bool,
+    pub illegal_policy: IllegalOpcodePolicy,
+    // why the CPU hung, it does nothing until reset
+    pub lockup: Option<CpuError>,
 }
 
 impl CPU {
@@ -122,11 +217,13 @@ impl CPU {
         CPU {
             registers: Registers::new(),
             memory: Memory::new(),
-            ime: true,
-            di: false,
-            ei: true,
+            ime: false,
+            ei: false,
             halt: false,
+            halt_bug: false,
             stop: false,
+            illegal_policy: IllegalOpcodePolicy::Lock,
+            lockup: None,
         }
     }
 
@@ -144,11 +241,6 @@ impl CPU {
         self.registers.flags & (flag as u8) != 0
     }
 
-    /// One cycle is the same as four ticks.
-    pub fn tick() {
-        todo!();
-    }
-
     pub fn get_instr(&mut self) -> u8 {
         let b = self.memory.read_byte(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
@@ -165,17 +257,147 @@ impl CPU {
     /// couple of types to undersand:
     ///
     /// - Immediate Values: accessed via memory using current PC value
-    pub fn exec(&mut self) {
+    ///
+    /// Returns the machine cycles the instruction took so the rest of the
+    /// system can be advanced by the same amount.
+    ///
+    /// Pending interrupts are serviced before the next instruction is fetched
+    /// and while halted or locked up the CPU idles one machine cycle at a
+    /// time.
+    ///
+    /// Illegal opcodes are handled as `illegal_policy` says. When it's an
+    /// error PC is left on the opcode.
+    pub fn exec(&mut self) -> Result<u8, CpuError> {
+        if self.lockup.is_some() {
+            return Ok(1);
+        }
+        if self.halt {
+            // any pending interrupt wakes the CPU, even when it can't be serviced
+            if self.pending_interrupts() == 0 {
+                return Ok(1);
+            }
+            self.halt = false;
+        }
+
+        if let Some(m_cycles) = self.interrupt_handler() {
+            return Ok(m_cycles);
+        }
+
+        if self.ei {
+            self.ei = false;
+            self.ime = true;
+        }
+
+        let addr = self.registers.pc;
         let opcode = self.get_instr();
-        // TODO: implement system ticks and cycles
-        let _m_cycles = operations(self, opcode);
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+        }
+        if matches!(
+            opcode,
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD
+        ) {
+            let error = CpuError::IllegalOpcode { opcode, addr };
+            if self.illegal_policy == IllegalOpcodePolicy::Lock {
+                self.lockup = Some(error);
+                return Ok(1);
+            }
+            self.registers.pc = addr;
+            return Err(error);
+        }
+        Ok(operations(self, opcode))
     }
 
-    fn interrupt_handler(&mut self) -> bool {
-        if !self.ime && self.halt {
-            return false;
+    /// Interrupts which are both requested and enabled.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.memory.get_register(IF) & self.memory.ie & 0x1F
+    }
+
+    /// Service the highest priority pending interrupt by pushing the PC and
+    /// jumping to the interrupt's vector, returning the machine cycles spent.
+    fn interrupt_handler(&mut self) -> Option<u8> {
+        if !self.ime {
+            return None;
         }
 
-        self.memory.register_read(IF) & self.memory.register_read(IE) != 0
+        let pending = self.pending_interrupts();
+        let interrupt = Interrupt::PRIORITY
+            .into_iter()
+            .find(|i| pending & i.mask() != 0)?;
+
+        self.ime = false;
+        let flags = self.memory.get_register(IF);
+        self.memory.set_register(IF, flags & !interrupt.mask());
+        self.registers.sp = self.registers.sp.wrapping_sub(2);
+        self.memory.write_word(self.registers.sp, self.registers.pc);
+        self.registers.pc = interrupt.vector();
+        Some(5)
+    }
+}
+
+impl Snapshot for Registers {
+    fn save(&self, w: &mut Writer) {
+        for r in [
+            self.acc, self.flags, self.b, self.c, self.d, self.e, self.high, self.low,
+        ] {
+            w.u8(r);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        for reg in [
+            &mut self.acc,
+            &mut self.flags,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.high,
+            &mut self.low,
+        ] {
+            *reg = r.u8()?;
+        }
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for CPU {
+    fn save(&self, w: &mut Writer) {
+        self.registers.save(w);
+        for flag in [self.ime, self.ei, self.halt, self.halt_bug, self.stop] {
+            w.bool(flag);
+        }
+        w.bool(self.lockup.is_some());
+        if let Some(CpuError::IllegalOpcode { opcode, addr }) = self.lockup {
+            w.u8(opcode);
+            w.u16(addr);
+        }
+        self.memory.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.registers.load(r)?;
+        for flag in [
+            &mut self.ime,
+            &mut self.ei,
+            &mut self.halt,
+            &mut self.halt_bug,
+            &mut self.stop,
+        ] {
+            *flag = r.bool()?;
+        }
+        self.lockup = match r.bool()? {
+            true => Some(CpuError::IllegalOpcode {
+                opcode: r.u8()?,
+                addr: r.u16()?,
+            }),
+            false => None,
+        };
+        self.memory.load(r)
     }
 }
@@ -0,0 +1,495 @@
+use std::fmt;
+
+/// An entry of the opcode tables. Operands in `text` are placeholders for the
+/// bytes after the opcode:
+///   * n8, n16 - immediate values
+///   * a8 - address in 0xFF00-0xFFFF
+///   * a16 - address
+///   * e8 - signed offset of a relative jump, shown as where it lands
+///   * s8 - signed offset added to the stack pointer
+///
+/// Cycles are machine cycles like `CPU::exec` returns, `taken` is what a
+/// conditional branch takes when the condition holds.
+#[derive(Debug, Clone, Copy)]
+struct Opcode {
+    text: &'static str,
+    len: u8,
+    cycles: u8,
+    taken: u8,
+}
+
+const fn op(text: &'static str, len: u8, cycles: u8) -> Opcode {
+    Opcode {
+        text,
+        len,
+        cycles,
+        taken: cycles,
+    }
+}
+
+const fn branch(text: &'static str, len: u8, cycles: u8, taken: u8) -> Opcode {
+    Opcode {
+        text,
+        len,
+        cycles,
+        taken,
+    }
+}
+
+// opcodes the CPU doesn't have, it locks up on them
+const ILLEGAL: Opcode = op("", 1, 1);
+
+// operands of the CB instructions, in the order the low 3 bits pick them
+const CB_REGISTERS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+// CB instructions 0x00-0x3F, picked by bits 3-5
+const CB_SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+// CB instructions 0x40-0xFF, picked by the top 2 bits, bits 3-5 are the bit
+const CB_BITS: [&str; 3] = ["bit", "res", "set"];
+
+#[rustfmt::skip]
+const OPCODES: [Opcode; 256] = [
+    // 0x00
+    op("nop", 1, 1),
+    op("ld bc, n16", 3, 3),
+    op("ld [bc], a", 1, 2),
+    op("inc bc", 1, 2),
+    op("inc b", 1, 1),
+    op("dec b", 1, 1),
+    op("ld b, n8", 2, 2),
+    op("rlca", 1, 1),
+    op("ld [a16], sp", 3, 5),
+    op("add hl, bc", 1, 2),
+    op("ld a, [bc]", 1, 2),
+    op("dec bc", 1, 2),
+    op("inc c", 1, 1),
+    op("dec c", 1, 1),
+    op("ld c, n8", 2, 2),
+    op("rrca", 1, 1),
+    // 0x10
+    op("stop", 2, 1),
+    op("ld de, n16", 3, 3),
+    op("ld [de], a", 1, 2),
+    op("inc de", 1, 2),
+    op("inc d", 1, 1),
+    op("dec d", 1, 1),
+    op("ld d, n8", 2, 2),
+    op("rla", 1, 1),
+    op("jr e8", 2, 3),
+    op("add hl, de", 1, 2),
+    op("ld a, [de]", 1, 2),
+    op("dec de", 1, 2),
+    op("inc e", 1, 1),
+    op("dec e", 1, 1),
+    op("ld e, n8", 2, 2),
+    op("rra", 1, 1),
+    // 0x20
+    branch("jr nz, e8", 2, 2, 3),
+    op("ld hl, n16", 3, 3),
+    op("ld [hl+], a", 1, 2),
+    op("inc hl", 1, 2),
+    op("inc h", 1, 1),
+    op("dec h", 1, 1),
+    op("ld h, n8", 2, 2),
+    op("daa", 1, 1),
+    branch("jr z, e8", 2, 2, 3),
+    op("add hl, hl", 1, 2),
+    op("ld a, [hl+]", 1, 2),
+    op("dec hl", 1, 2),
+    op("inc l", 1, 1),
+    op("dec l", 1, 1),
+    op("ld l, n8", 2, 2),
+    op("cpl", 1, 1),
+    // 0x30
+    branch("jr nc, e8", 2, 2, 3),
+    op("ld sp, n16", 3, 3),
+    op("ld [hl-], a", 1, 2),
+    op("inc sp", 1, 2),
+    op("inc [hl]", 1, 3),
+    op("dec [hl]", 1, 3),
+    op("ld [hl], n8", 2, 3),
+    op("scf", 1, 1),
+    branch("jr c, e8", 2, 2, 3),
+    op("add hl, sp", 1, 2),
+    op("ld a, [hl-]", 1, 2),
+    op("dec sp", 1, 2),
+    op("inc a", 1, 1),
+    op("dec a", 1, 1),
+    op("ld a, n8", 2, 2),
+    op("ccf", 1, 1),
+    // 0x40
+    op("ld b, b", 1, 1),
+    op("ld b, c", 1, 1),
+    op("ld b, d", 1, 1),
+    op("ld b, e", 1, 1),
+    op("ld b, h", 1, 1),
+    op("ld b, l", 1, 1),
+    op("ld b, [hl]", 1, 2),
+    op("ld b, a", 1, 1),
+    op("ld c, b", 1, 1),
+    op("ld c, c", 1, 1),
+    op("ld c, d", 1, 1),
+    op("ld c, e", 1, 1),
+    op("ld c, h", 1, 1),
+    op("ld c, l", 1, 1),
+    op("ld c, [hl]", 1, 2),
+    op("ld c, a", 1, 1),
+    // 0x50
+    op("ld d, b", 1, 1),
+    op("ld d, c", 1, 1),
+    op("ld d, d", 1, 1),
+    op("ld d, e", 1, 1),
+    op("ld d, h", 1, 1),
+    op("ld d, l", 1, 1),
+    op("ld d, [hl]", 1, 2),
+    op("ld d, a", 1, 1),
+    op("ld e, b", 1, 1),
+    op("ld e, c", 1, 1),
+    op("ld e, d", 1, 1),
+    op("ld e, e", 1, 1),
+    op("ld e, h", 1, 1),
+    op("ld e, l", 1, 1),
+    op("ld e, [hl]", 1, 2),
+    op("ld e, a", 1, 1),
+    // 0x60
+    op("ld h, b", 1, 1),
+    op("ld h, c", 1, 1),
+    op("ld h, d", 1, 1),
+    op("ld h, e", 1, 1),
+    op("ld h, h", 1, 1),
+    op("ld h, l", 1, 1),
+    op("ld h, [hl]", 1, 2),
+    op("ld h, a", 1, 1),
+    op("ld l, b", 1, 1),
+    op("ld l, c", 1, 1),
+    op("ld l, d", 1, 1),
+    op("ld l, e", 1, 1),
+    op("ld l, h", 1, 1),
+    op("ld l, l", 1, 1),
+    op("ld l, [hl]", 1, 2),
+    op("ld l, a", 1, 1),
+    // 0x70
+    op("ld [hl], b", 1, 2),
+    op("ld [hl], c", 1, 2),
+    op("ld [hl], d", 1, 2),
+    op("ld [hl], e", 1, 2),
+    op("ld [hl], h", 1, 2),
+    op("ld [hl], l", 1, 2),
+    op("halt", 1, 1),
+    op("ld [hl], a", 1, 2),
+    op("ld a, b", 1, 1),
+    op("ld a, c", 1, 1),
+    op("ld a, d", 1, 1),
+    op("ld a, e", 1, 1),
+    op("ld a, h", 1, 1),
+    op("ld a, l", 1, 1),
+    op("ld a, [hl]", 1, 2),
+    op("ld a, a", 1, 1),
+    // 0x80
+    op("add a, b", 1, 1),
+    op("add a, c", 1, 1),
+    op("add a, d", 1, 1),
+    op("add a, e", 1, 1),
+    op("add a, h", 1, 1),
+    op("add a, l", 1, 1),
+    op("add a, [hl]", 1, 2),
+    op("add a, a", 1, 1),
+    op("adc a, b", 1, 1),
+    op("adc a, c", 1, 1),
+    op("adc a, d", 1, 1),
+    op("adc a, e", 1, 1),
+    op("adc a, h", 1, 1),
+    op("adc a, l", 1, 1),
+    op("adc a, [hl]", 1, 2),
+    op("adc a, a", 1, 1),
+    // 0x90
+    op("sub b", 1, 1),
+    op("sub c", 1, 1),
+    op("sub d", 1, 1),
+    op("sub e", 1, 1),
+    op("sub h", 1, 1),
+    op("sub l", 1, 1),
+    op("sub [hl]", 1, 2),
+    op("sub a", 1, 1),
+    op("sbc a, b", 1, 1),
+    op("sbc a, c", 1, 1),
+    op("sbc a, d", 1, 1),
+    op("sbc a, e", 1, 1),
+    op("sbc a, h", 1, 1),
+    op("sbc a, l", 1, 1),
+    op("sbc a, [hl]", 1, 2),
+    op("sbc a, a", 1, 1),
+    // 0xA0
+    op("and b", 1, 1),
+    op("and c", 1, 1),
+    op("and d", 1, 1),
+    op("and e", 1, 1),
+    op("and h", 1, 1),
+    op("and l", 1, 1),
+    op("and [hl]", 1, 2),
+    op("and a", 1, 1),
+    op("xor b", 1, 1),
+    op("xor c", 1, 1),
+    op("xor d", 1, 1),
+    op("xor e", 1, 1),
+    op("xor h", 1, 1),
+    op("xor l", 1, 1),
+    op("xor [hl]", 1, 2),
+    op("xor a", 1, 1),
+    // 0xB0
+    op("or b", 1, 1),
+    op("or c", 1, 1),
+    op("or d", 1, 1),
+    op("or e", 1, 1),
+    op("or h", 1, 1),
+    op("or l", 1, 1),
+    op("or [hl]", 1, 2),
+    op("or a", 1, 1),
+    op("cp b", 1, 1),
+    op("cp c", 1, 1),
+    op("cp d", 1, 1),
+    op("cp e", 1, 1),
+    op("cp h", 1, 1),
+    op("cp l", 1, 1),
+    op("cp [hl]", 1, 2),
+    op("cp a", 1, 1),
+    // 0xC0
+    branch("ret nz", 1, 2, 5),
+    op("pop bc", 1, 3),
+    branch("jp nz, a16", 3, 3, 4),
+    op("jp a16", 3, 4),
+    branch("call nz, a16", 3, 3, 6),
+    op("push bc", 1, 4),
+    op("add a, n8", 2, 2),
+    op("rst $00", 1, 4),
+    branch("ret z", 1, 2, 5),
+    op("ret", 1, 4),
+    branch("jp z, a16", 3, 3, 4),
+    op("prefix", 2, 1),
+    branch("call z, a16", 3, 3, 6),
+    op("call a16", 3, 6),
+    op("adc a, n8", 2, 2),
+    op("rst $08", 1, 4),
+    // 0xD0
+    branch("ret nc", 1, 2, 5),
+    op("pop de", 1, 3),
+    branch("jp nc, a16", 3, 3, 4),
+    ILLEGAL,
+    branch("call nc, a16", 3, 3, 6),
+    op("push de", 1, 4),
+    op("sub n8", 2, 2),
+    op("rst $10", 1, 4),
+    branch("ret c", 1, 2, 5),
+    op("reti", 1, 4),
+    branch("jp c, a16", 3, 3, 4),
+    ILLEGAL,
+    branch("call c, a16", 3, 3, 6),
+    ILLEGAL,
+    op("sbc a, n8", 2, 2),
+    op("rst $18", 1, 4),
+    // 0xE0
+    op("ldh [a8], a", 2, 3),
+    op("pop hl", 1, 3),
+    op("ldh [c], a", 1, 2),
+    ILLEGAL,
+    ILLEGAL,
+    op("push hl", 1, 4),
+    op("and n8", 2, 2),
+    op("rst $20", 1, 4),
+    op("add sp, s8", 2, 4),
+    op("jp hl", 1, 1),
+    op("ld [a16], a", 3, 4),
+    ILLEGAL,
+    ILLEGAL,
+    ILLEGAL,
+    op("xor n8", 2, 2),
+    op("rst $28", 1, 4),
+    // 0xF0
+    op("ldh a, [a8]", 2, 3),
+    op("pop af", 1, 3),
+    op("ldh a, [c]", 1, 2),
+    op("di", 1, 1),
+    ILLEGAL,
+    op("push af", 1, 4),
+    op("or n8", 2, 2),
+    op("rst $30", 1, 4),
+    op("ld hl, sp+s8", 2, 3),
+    op("ld sp, hl", 1, 2),
+    op("ld a, [a16]", 3, 4),
+    op("ei", 1, 1),
+    ILLEGAL,
+    ILLEGAL,
+    op("cp n8", 2, 2),
+    op("rst $38", 1, 4),
+];
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub text: String,
+    // the opcode and its operands
+    pub bytes: Vec<u8>,
+    // machine cycles taken
+    pub cycles: u8,
+    // machine cycles taken by a conditional branch when the condition holds
+    pub taken: Option<u8>,
+    // not an opcode the CPU has, shown as a data byte
+    pub illegal: bool,
+}
+
+impl Instruction {
+    /// Bytes taken up, the opcode included.
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    /// A line of a listing with the address and bytes,
+    /// `0150: c3 13 02  jp $0213`.
+    pub fn line(&self, addr: u16) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{b:02x}")).collect();
+        format!("{addr:04x}: {:<8}  {}", bytes.join(" "), self.text)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Decode the instruction at `addr`, fetching its bytes with `read`.
+pub fn disassemble(addr: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+    let opcode = read(addr);
+    if opcode == 0xCB {
+        return prefixed(read(addr.wrapping_add(1)));
+    }
+    let entry = OPCODES[opcode as usize];
+    let bytes: Vec<u8> = (0..entry.len as u16)
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect();
+    if entry.text.is_empty() {
+        return Instruction {
+            text: format!("db ${opcode:02x}"),
+            bytes,
+            cycles: entry.cycles,
+            taken: None,
+            illegal: true,
+        };
+    }
+    let n8 = bytes.get(1).copied().unwrap_or_default();
+    let n16 = u16::from_le_bytes([n8, bytes.get(2).copied().unwrap_or_default()]);
+    let e8 = n8 as i8;
+    let text = entry
+        .text
+        .replace("n16", &format!("${n16:04x}"))
+        .replace("a16", &format!("${n16:04x}"))
+        .replace("n8", &format!("${n8:02x}"))
+        .replace("a8", &format!("${:04x}", 0xFF00 | n8 as u16))
+        .replace(
+            "e8",
+            &format!(
+                "${:04x}",
+                addr.wrapping_add(2).wrapping_add_signed(e8 as i16)
+            ),
+        )
+        .replace("+s8", &format!("{e8:+}"))
+        .replace("s8", &format!("{e8}"));
+    Instruction {
+        text,
+        bytes,
+        cycles: entry.cycles,
+        taken: (entry.taken != entry.cycles).then_some(entry.taken),
+        illegal: false,
+    }
+}
+
+fn prefixed(opcode: u8) -> Instruction {
+    let register = CB_REGISTERS[opcode as usize & 7];
+    let y = (opcode >> 3) & 7;
+    let (text, memory_cycles) = match opcode >> 6 {
+        0 => (format!("{} {register}", CB_SHIFTS[y as usize]), 4),
+        // bit only reads (hl)
+        1 => (format!("bit {y}, {register}"), 3),
+        x => (format!("{} {y}, {register}", CB_BITS[x as usize - 1]), 4),
+    };
+    Instruction {
+        text,
+        bytes: vec![0xCB, opcode],
+        cycles: if register == "[hl]" { memory_cycles } else { 2 },
+        taken: None,
+        illegal: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    fn decode(bytes: &[u8]) -> Instruction {
+        disassemble(0x150, |a| {
+            bytes.get(a as usize - 0x150).copied().unwrap_or(0)
+        })
+    }
+
+    #[test]
+    fn disassemble_test() {
+        assert_eq!(decode(&[0x00]).text, "nop");
+        assert_eq!(
+            decode(&[0xC3, 0x13, 0x02]).line(0x150),
+            "0150: c3 13 02  jp $0213"
+        );
+        assert_eq!(decode(&[0x3E, 0x42]).text, "ld a, $42");
+        assert_eq!(decode(&[0x7E]).text, "ld a, [hl]");
+        assert_eq!(decode(&[0xE0, 0x44]).text, "ldh [$ff44], a");
+        assert_eq!(decode(&[0x20, 0xFE]).text, "jr nz, $0150");
+        assert_eq!(decode(&[0xF8, 0xFE]).text, "ld hl, sp-2");
+        assert_eq!(decode(&[0xE8, 0x05]).text, "add sp, 5");
+        assert_eq!(decode(&[0xCB, 0x37]).text, "swap a");
+        assert_eq!(decode(&[0xCB, 0x7E]).text, "bit 7, [hl]");
+        assert_eq!(decode(&[0xCB, 0xC1]).text, "set 0, c");
+
+        let call = decode(&[0xC4, 0x00, 0x40]);
+        assert_eq!((call.size(), call.cycles, call.taken), (3, 3, Some(6)));
+        let illegal = decode(&[0xD3]);
+        assert!(illegal.illegal);
+        assert_eq!(illegal.text, "db $d3");
+    }
+
+    /// Machine cycles the CPU reports running the instruction with the flags
+    /// set or cleared.
+    fn run(bytes: &[u8], flags: u8) -> u8 {
+        let mut cpu = CPU::new();
+        for (i, b) in bytes.iter().enumerate() {
+            cpu.memory.write_byte(0xC000 + i as u16, *b);
+        }
+        cpu.registers.pc = 0xC000;
+        cpu.registers.sp = 0xD000;
+        cpu.registers.flags = flags;
+        cpu.exec().unwrap()
+    }
+
+    #[test]
+    fn cycles_test() {
+        for opcode in 0..=0xFFu8 {
+            // halt idles the CPU, and the CB table is checked below
+            if matches!(opcode, 0x76 | 0xCB) {
+                continue;
+            }
+            let i = decode(&[opcode]);
+            if i.illegal {
+                continue;
+            }
+            let cycles = [run(&[opcode, 0, 0], 0x00), run(&[opcode, 0, 0], 0xF0)];
+            let expected = [i.cycles, i.taken.unwrap_or(i.cycles)];
+            assert!(
+                cycles == expected || cycles == [expected[1], expected[0]],
+                "{} took {cycles:?}",
+                i.text
+            );
+        }
+        for opcode in 0..=0xFFu8 {
+            let i = decode(&[0xCB, opcode]);
+            assert_eq!(run(&[0xCB, opcode], 0), i.cycles, "{}", i.text);
+        }
+    }
+}
@@ -23,8 +23,6 @@ fn ld_r8_n8(c: &mut CPU, r: Reg) -> u8 {
 fn ld_r8_r8(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
     let v = read_reg(c, &r2);
     write_reg(c, &r1, v);
-    //std::thread::sleep(std::time::Duration::from_millis(1));
-    c.memory.print_serial();
     1
 }
 
@@ -39,7 +37,7 @@ fn ld_r16m_n8(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
     let v = c.get_instr();
     let addr = (read_reg(c, &r1) as u16) << 8 | read_reg(c, &r2) as u16;
     c.memory.write_byte(addr, v);
-    2
+    3
 }
 
 fn ld_r16_n16(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
@@ -70,7 +68,7 @@ fn ld_r16m_a(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
 fn ld_a16m_sp(c: &mut CPU) -> u8 {
     let addr = c.get_word_instr();
     c.memory.write_word(addr, c.registers.sp);
-    3
+    5
 }
 
 fn ld_a8m_a(c: &mut CPU) -> u8 {
@@ -92,13 +90,8 @@ fn ld_a_r16m(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
 }
 
 fn ld_hl_spe8(c: &mut CPU) -> u8 {
-    let mut hl = c.registers.sp;
-    let b = c.get_instr() as i16 as u16;
-    c.set_flag(ALUFlag::N, false);
-    c.set_flag(ALUFlag::Z, false);
-    c.set_flag(ALUFlag::H, (hl & 0x000F) + (b & 0x000F) > 0x000F);
-    c.set_flag(ALUFlag::C, (hl & 0x00FF) + (b & 0x00FF) > 0x00FF);
-    hl = hl.wrapping_add(b);
+    let e = c.get_instr();
+    let hl = add_sp_u8(c, e);
     c.registers.high = (hl >> 8) as u8;
     c.registers.low = hl as u8;
     3
@@ -112,7 +105,7 @@ fn ld_sp_hl(c: &mut CPU) -> u8 {
 fn ld_hlim_a(c: &mut CPU) -> u8 {
     let mut hl = (c.registers.high as u16) << 8 | c.registers.low as u16;
     c.memory.write_byte(hl, c.registers.acc);
-    hl += 1;
+    hl = hl.wrapping_add(1);
     c.registers.high = (hl >> 8) as u8;
     c.registers.low = hl as u8;
     2
@@ -121,7 +114,7 @@ fn ld_hlim_a(c: &mut CPU) -> u8 {
 fn ld_hldm_a(c: &mut CPU) -> u8 {
     let mut hl = (c.registers.high as u16) << 8 | c.registers.low as u16;
     c.memory.write_byte(hl, c.registers.acc);
-    hl -= 1;
+    hl = hl.wrapping_sub(1);
     c.registers.high = (hl >> 8) as u8;
     c.registers.low = hl as u8;
     2
@@ -190,37 +183,29 @@ fn inc_r8(c: &mut CPU, r: Reg) -> u8 {
     let vv = v.wrapping_add(1);
     write_reg(c, &r, vv);
     c.set_flag(ALUFlag::Z, vv == 0);
-    c.set_flag(ALUFlag::H, (0x18 & v == 0x08) && (0x10 & vv != 0));
+    c.set_flag(ALUFlag::H, v & 0x0F == 0x0F);
     c.set_flag(ALUFlag::N, false);
     1
 }
 
 fn dec_r16m(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
-    let addr = (read_reg(c, &r1) as usize) << 8 | read_reg(c, &r2) as usize;
-    let v = c.memory.ram[addr];
+    let addr = (read_reg(c, &r1) as u16) << 8 | read_reg(c, &r2) as u16;
+    let v = c.memory.read_byte(addr);
     let vv = v.wrapping_sub(1);
-    c.memory.ram[addr] = vv;
-    if vv & 0b0000_1000 != 0 && v & 0b0001_0000 != 0 && v & 0b0000_1000 == 0 {
-        c.set_flag(ALUFlag::H, true);
-    };
-    if vv == 0 {
-        c.set_flag(ALUFlag::Z, true);
-    }
+    c.memory.write_byte(addr, vv);
+    c.set_flag(ALUFlag::H, v & 0x0F == 0);
+    c.set_flag(ALUFlag::Z, vv == 0);
     c.set_flag(ALUFlag::N, true);
     3
 }
 
 fn inc_r16m(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
-    let addr = (read_reg(c, &r1) as usize) << 8 | read_reg(c, &r2) as usize;
-    let v = c.memory.ram[addr];
+    let addr = (read_reg(c, &r1) as u16) << 8 | read_reg(c, &r2) as u16;
+    let v = c.memory.read_byte(addr);
     let vv = v.wrapping_add(1);
-    c.memory.ram[addr] = vv;
-    if vv & 0b0001_0000 != 0 && v & 0b0001_0000 == 0 {
-        c.set_flag(ALUFlag::H, true);
-    }
-    if vv == 0 {
-        c.set_flag(ALUFlag::Z, true);
-    }
+    c.memory.write_byte(addr, vv);
+    c.set_flag(ALUFlag::H, v & 0x0F == 0x0F);
+    c.set_flag(ALUFlag::Z, vv == 0);
     c.set_flag(ALUFlag::N, false);
     3
 }
@@ -230,7 +215,7 @@ fn dec_r8(c: &mut CPU, r: Reg) -> u8 {
     let vv = v.wrapping_sub(1);
     write_reg(c, &r, vv);
     c.set_flag(ALUFlag::Z, vv == 0);
-    c.set_flag(ALUFlag::H, (0x18 & v == 0x10) && (0x08 & vv != 0));
+    c.set_flag(ALUFlag::H, v & 0x0F == 0);
     c.set_flag(ALUFlag::N, true);
     1
 }
@@ -253,7 +238,7 @@ fn rlca(c: &mut CPU) -> u8 {
     let v = (rv << 1) | if carry { 1 } else { 0 };
     c.registers.acc = v;
     c.set_flag(ALUFlag::C, carry);
-    c.set_flag(ALUFlag::Z, v == 0);
+    c.set_flag(ALUFlag::Z, false);
     c.set_flag(ALUFlag::N, false);
     c.set_flag(ALUFlag::H, false);
     1
@@ -307,19 +292,30 @@ fn add_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
 }
 
 /// Generically do the ALU add operation for 16 bit values and set the proper
-/// flags
+/// flags, the zero flag is left untouched
 fn add_u16(c: &mut CPU, v1: u16, v2: u16) -> u16 {
     let overflow_result = v1.wrapping_add(v2);
     match v1.checked_add(v2) {
         Some(_) => c.set_flag(ALUFlag::C, false),
         None => c.set_flag(ALUFlag::C, true),
     }
-    c.set_flag(ALUFlag::H, (0x00FF & v1) + (0x00FF & v2) > 0x00FF);
+    c.set_flag(ALUFlag::H, (0x0FFF & v1) + (0x0FFF & v2) > 0x0FFF);
     c.set_flag(ALUFlag::N, false);
-    c.set_flag(ALUFlag::Z, overflow_result == 0);
     overflow_result
 }
 
+/// Add a signed offset to SP, the flags come from the unsigned addition of
+/// the low byte
+fn add_sp_u8(c: &mut CPU, e: u8) -> u16 {
+    let sp = c.registers.sp;
+    let e = e as u16;
+    c.set_flag(ALUFlag::Z, false);
+    c.set_flag(ALUFlag::N, false);
+    c.set_flag(ALUFlag::H, (sp & 0x000F) + (e & 0x000F) > 0x000F);
+    c.set_flag(ALUFlag::C, (sp & 0x00FF) + (e & 0x00FF) > 0x00FF);
+    sp.wrapping_add(e as u8 as i8 as u16)
+}
+
 /// Generically do the ALU adc operation for 8 bit values and set the proper
 /// flags
 fn adc_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
@@ -340,10 +336,7 @@ fn adc_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
 fn sub_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
     let result = v1.wrapping_sub(v2);
     c.set_flag(ALUFlag::C, v2 > v1);
-    c.set_flag(
-        ALUFlag::H,
-        ((0xF0 & v1).wrapping_sub(0xF0 & v2) ^ 0x0F) < 0x0F,
-    );
+    c.set_flag(ALUFlag::H, (0x0F & v2) > (0x0F & v1));
     c.set_flag(ALUFlag::Z, result == 0);
     c.set_flag(ALUFlag::N, true);
     result
@@ -354,11 +347,8 @@ fn sub_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
 fn sbc_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
     let carry = if c.check_flag(ALUFlag::C) { 1 } else { 0 };
     let result = v1.wrapping_sub(v2).wrapping_sub(carry);
-    c.set_flag(ALUFlag::C, (v2 + carry) > v1);
-    c.set_flag(
-        ALUFlag::H,
-        ((0xF0 & v1).wrapping_sub(0xF0 & v2).wrapping_sub(carry) ^ 0x0F) < 0x0F,
-    );
+    c.set_flag(ALUFlag::C, (v2 as u16 + carry as u16) > v1 as u16);
+    c.set_flag(ALUFlag::H, (0x0F & v2) + carry > (0x0F & v1));
     c.set_flag(ALUFlag::Z, result == 0);
     c.set_flag(ALUFlag::N, true);
     result
@@ -366,14 +356,7 @@ fn sbc_u8(c: &mut CPU, v1: u8, v2: u8) -> u8 {
 
 /// Only sets flags, no return value needed
 fn cp_u8(c: &mut CPU, v1: u8, v2: u8) {
-    let result = v1.wrapping_sub(v2);
-    c.set_flag(ALUFlag::C, v2 > v1);
-    c.set_flag(
-        ALUFlag::H,
-        (0xF0 & v1).wrapping_sub(0xF0 & v2) ^ 0x0F < 0x0F,
-    );
-    c.set_flag(ALUFlag::Z, result == 0);
-    c.set_flag(ALUFlag::N, true);
+    sub_u8(c, v1, v2);
 }
 
 fn add_hl_r16(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
@@ -445,10 +428,8 @@ fn add_r16_sp(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
 }
 
 fn add_sp_e8(c: &mut CPU) -> u8 {
-    let a = c.registers.sp;
-    let b = c.get_instr() as i8 as i16 as u16;
-    c.registers.sp = add_u16(c, a, b);
-    c.set_flag(ALUFlag::Z, false);
+    let e = c.get_instr();
+    c.registers.sp = add_sp_u8(c, e);
     4
 }
 
@@ -536,7 +517,7 @@ fn and_r8_r16m(c: &mut CPU, r1: Reg, r2: Reg, r3: Reg) -> u8 {
     c.set_flag(ALUFlag::H, true);
     c.set_flag(ALUFlag::C, false);
     c.set_flag(ALUFlag::N, false);
-    1
+    2
 }
 
 fn xor_r8_r8(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
@@ -560,7 +541,7 @@ fn xor_r8_n8(c: &mut CPU, r1: Reg) -> u8 {
     c.set_flag(ALUFlag::H, false);
     c.set_flag(ALUFlag::C, false);
     c.set_flag(ALUFlag::N, false);
-    1
+    2
 }
 
 fn xor_r8_r16m(c: &mut CPU, r1: Reg, r2: Reg, r3: Reg) -> u8 {
@@ -573,7 +554,7 @@ fn xor_r8_r16m(c: &mut CPU, r1: Reg, r2: Reg, r3: Reg) -> u8 {
     c.set_flag(ALUFlag::H, false);
     c.set_flag(ALUFlag::C, false);
     c.set_flag(ALUFlag::N, false);
-    1
+    2
 }
 
 fn or_r8_r8(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
@@ -610,7 +591,7 @@ fn or_r8_r16m(c: &mut CPU, r1: Reg, r2: Reg, r3: Reg) -> u8 {
     c.set_flag(ALUFlag::H, false);
     c.set_flag(ALUFlag::C, false);
     c.set_flag(ALUFlag::N, false);
-    1
+    2
 }
 
 fn cp_r8_r8(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
@@ -624,7 +605,7 @@ fn cp_r8_n8(c: &mut CPU, r1: Reg) -> u8 {
     let v = read_reg(c, &r1);
     let v2 = c.get_instr();
     cp_u8(c, v, v2);
-    1
+    2
 }
 
 fn cp_r8_r16m(c: &mut CPU, r1: Reg, r2: Reg, r3: Reg) -> u8 {
@@ -643,17 +624,20 @@ fn scf(c: &mut CPU) -> u8 {
 }
 
 fn stop_n8(c: &mut CPU) -> u8 {
-    // mostly used to switch speeds, ignoring for now
-    c.stop = true;
-    4
+    // the byte after STOP is skipped, assemblers pad it with a zero
+    c.registers.pc = c.registers.pc.wrapping_add(1);
+    // mostly used to switch speeds, the CPU pauses for a while after
+    // switching which isn't emulated
+    if !c.memory.switch_speed() {
+        c.stop = true;
+    }
+    1
 }
 
 fn jr_e8(c: &mut CPU) -> u8 {
     let offset = c.get_instr() as i8;
     // convert to u32 to expand the bit range before converting to i32, so sign is not affected
     c.registers.pc = ((c.registers.pc as u32 as i32) + (offset as i32)) as u16;
-    c.memory.print_serial();
-    //std::thread::sleep(std::time::Duration::from_millis(100));
     3
 }
 
@@ -709,7 +693,10 @@ fn daa(c: &mut CPU) -> u8 {
         adjust |= 0x06;
     };
 
+    // after a subtraction only the flags tell whether digits were borrowed
     if c.check_flag(ALUFlag::N) {
+        acc = acc.wrapping_sub(adjust);
+    } else {
         if acc & 0x0F > 0x09 {
             adjust |= 0x06;
         };
@@ -717,8 +704,6 @@ fn daa(c: &mut CPU) -> u8 {
             adjust |= 0x60;
         };
         acc = acc.wrapping_add(adjust);
-    } else {
-        acc = acc.wrapping_sub(adjust);
     }
 
     c.set_flag(ALUFlag::C, adjust >= 0x60);
@@ -746,14 +731,20 @@ fn ccf(c: &mut CPU) -> u8 {
 }
 
 fn halt(c: &mut CPU) -> u8 {
-    c.halt = true;
+    // with interrupts disabled but already pending the CPU doesn't halt and
+    // fails to increment the PC on the following fetch
+    if !c.ime && c.pending_interrupts() != 0 {
+        c.halt_bug = true;
+    } else {
+        c.halt = true;
+    }
     1
 }
 
 fn ret(c: &mut CPU) -> u8 {
     let v = c.memory.read_word(c.registers.sp);
     c.registers.pc = v;
-    c.registers.sp += 2;
+    c.registers.sp = c.registers.sp.wrapping_add(2);
     4
 }
 
@@ -761,7 +752,7 @@ fn ret_cc(c: &mut CPU, flag: ALUFlag, set: bool) -> u8 {
     if c.check_flag(flag) == set {
         let v = c.memory.read_word(c.registers.sp);
         c.registers.pc = v;
-        c.registers.sp += 2;
+        c.registers.sp = c.registers.sp.wrapping_add(2);
         5
     } else {
         2
@@ -770,12 +761,12 @@ fn ret_cc(c: &mut CPU, flag: ALUFlag, set: bool) -> u8 {
 
 fn jp_r16(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
     c.registers.pc = (read_reg(c, &r1) as u16) << 8 | read_reg(c, &r2) as u16;
-    4
+    1
 }
 
 fn jp_a16(c: &mut CPU) -> u8 {
     c.registers.pc = c.get_word_instr();
-    3
+    4
 }
 
 fn jp_a16_cc(c: &mut CPU, flag: ALUFlag, set: bool) -> u8 {
@@ -789,19 +780,22 @@ fn jp_a16_cc(c: &mut CPU, flag: ALUFlag, set: bool) -> u8 {
 }
 
 fn call_a16(c: &mut CPU) -> u8 {
-    c.registers.sp -= 2;
-    c.memory.write_word(c.registers.sp, c.registers.pc + 2);
+    c.registers.sp = c.registers.sp.wrapping_sub(2);
+    c.memory
+        .write_word(c.registers.sp, c.registers.pc.wrapping_add(2));
     c.registers.pc = c.get_word_instr();
     6
 }
 
 fn call_a16_cc(c: &mut CPU, flag: ALUFlag, set: bool) -> u8 {
     if c.check_flag(flag) == set {
-        c.registers.sp -= 2;
-        c.memory.write_word(c.registers.sp, c.registers.pc + 2);
+        c.registers.sp = c.registers.sp.wrapping_sub(2);
+        c.memory
+            .write_word(c.registers.sp, c.registers.pc.wrapping_add(2));
         c.registers.pc = c.get_word_instr();
         6
     } else {
+        c.registers.pc = c.registers.pc.wrapping_add(2);
         3
     }
 }
@@ -810,12 +804,12 @@ fn pop_r16(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
     let v = c.memory.read_word(c.registers.sp);
     write_reg(c, &r1, (v >> 8) as u8);
     write_reg(c, &r2, v as u8);
-    c.registers.sp += 2;
+    c.registers.sp = c.registers.sp.wrapping_add(2);
     3
 }
 
 fn push_r16(c: &mut CPU, r1: Reg, r2: Reg) -> u8 {
-    c.registers.sp -= 2;
+    c.registers.sp = c.registers.sp.wrapping_sub(2);
     let v = (read_reg(c, &r1) as u16) << 8 | read_reg(c, &r2) as u16;
     c.memory.write_word(c.registers.sp, v);
     4
@@ -830,18 +824,21 @@ fn rst(c: &mut CPU, val: u16) -> u8 {
 
 fn prefix(c: &mut CPU) -> u8 {
     let opcode = c.get_instr();
-    1 + prefix_instructions::operation(c, opcode)
+    // prefixed instructions count the prefix in their cycles
+    prefix_instructions::operation(c, opcode)
 }
 
 fn reti(c: &mut CPU) -> u8 {
     c.registers.pc = c.memory.read_word(c.registers.sp);
-    c.registers.sp += 2;
-    c.ei = true;
+    c.registers.sp = c.registers.sp.wrapping_add(2);
+    // unlike `ei` there is no delay
+    c.ime = true;
     4
 }
 
 fn di(c: &mut CPU) -> u8 {
-    c.di = true;
+    c.ime = false;
+    c.ei = false;
     1
 }
 
@@ -873,7 +870,8 @@ pub(crate) fn write_reg(c: &mut CPU, r: &Reg, v: u8) {
         Reg::E => c.registers.e = v,
         Reg::H => c.registers.high = v,
         Reg::L => c.registers.low = v,
-        Reg::FLAGS => c.registers.flags = v,
+        // the lower nibble of the flags is always 0
+        Reg::FLAGS => c.registers.flags = v & 0xF0,
     }
 }
 
@@ -881,7 +879,6 @@ pub(crate) fn write_reg(c: &mut CPU, r: &Reg, v: u8) {
 /// ticks_ (T-states). To convert M-cycles to T-states:
 ///   t_states = m_cycles * 4
 pub(crate) fn operations(c: &mut CPU, opcode: u8) -> u8 {
-    println!("opcode: {:2x}", opcode);
     match opcode {
         0x0 => 1,
         0x1 => ld_r16_n16(c, Reg::B, Reg::C),
@@ -1139,9 +1136,6 @@ pub(crate) fn operations(c: &mut CPU, opcode: u8) -> u8 {
         // no FD
         0xFE => cp_r8_n8(c, Reg::A),
         0xFF => rst(c, 0x38),
-        _ => {
-            eprintln!("OpCode is not implemented: {}", opcode);
-            1
-        }
+        _ => unreachable!("illegal opcode {opcode:02x} is handled by exec"),
     }
 }
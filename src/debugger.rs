@@ -0,0 +1,578 @@
+use crate::cpu::{CpuError, Registers};
+use crate::system::System;
+use std::fmt;
+use std::str::FromStr;
+
+/// A register a breakpoint condition can look at, pairs read as 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn read(&self, r: &Registers) -> u16 {
+        let pair = |high: u8, low: u8| (high as u16) << 8 | low as u16;
+        match self {
+            Register::A => r.acc as u16,
+            Register::F => r.flags as u16,
+            Register::B => r.b as u16,
+            Register::C => r.c as u16,
+            Register::D => r.d as u16,
+            Register::E => r.e as u16,
+            Register::H => r.high as u16,
+            Register::L => r.low as u16,
+            Register::AF => pair(r.acc, r.flags),
+            Register::BC => pair(r.b, r.c),
+            Register::DE => pair(r.d, r.e),
+            Register::HL => pair(r.high, r.low),
+            Register::SP => r.sp,
+            Register::PC => r.pc,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "a" => Register::A,
+            "f" => Register::F,
+            "b" => Register::B,
+            "c" => Register::C,
+            "d" => Register::D,
+            "e" => Register::E,
+            "h" => Register::H,
+            "l" => Register::L,
+            "af" => Register::AF,
+            "bc" => Register::BC,
+            "de" => Register::DE,
+            "hl" => Register::HL,
+            "sp" => Register::SP,
+            "pc" => Register::PC,
+            _ => return Err(DebugError::Register(s.to_owned())),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    const ALL: [(&str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+}
+
+/// A register compared with a value, like `a == 42` or `hl >= c000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, r: &Registers) -> bool {
+        let v = self.register.read(r);
+        match self.comparison {
+            Comparison::Eq => v == self.value,
+            Comparison::Ne => v != self.value,
+            Comparison::Lt => v < self.value,
+            Comparison::Le => v <= self.value,
+            Comparison::Gt => v > self.value,
+            Comparison::Ge => v >= self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (op, comparison) = Comparison::ALL
+            .into_iter()
+            .find(|(op, _)| s.contains(op))
+            .ok_or_else(|| DebugError::Condition(s.to_owned()))?;
+        let (register, value) = s.split_once(op).unwrap();
+        Ok(Condition {
+            register: register.trim().parse()?,
+            comparison,
+            value: parse_number(value.trim())?,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (op, _) = Comparison::ALL
+            .into_iter()
+            .find(|(_, c)| *c == self.comparison)
+            .unwrap();
+        let register = format!("{:?}", self.register).to_ascii_lowercase();
+        write!(f, "{register} {op} {:x}", self.value)
+    }
+}
+
+/// Numbers are always hexadecimal, with an optional `0x` or `$` prefix.
+pub fn parse_number(s: &str) -> Result<u16, DebugError> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| DebugError::Number(s.to_owned()))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DebugError {
+    Number(String),
+    Register(String),
+    Condition(String),
+    UnknownCommand(String),
+    // a command's arguments are missing or invalid, holds its usage
+    Usage(&'static str),
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugError::Number(s) => write!(f, "{s:?} isn't a hexadecimal number"),
+            DebugError::Register(s) => write!(f, "unknown register {s:?}"),
+            DebugError::Condition(s) => write!(f, "invalid condition {s:?}"),
+            DebugError::UnknownCommand(s) => write!(f, "unknown command {s:?}, try help"),
+            DebugError::Usage(usage) => write!(f, "usage: {usage}"),
+        }
+    }
+}
+
+impl std::error::Error for DebugError {}
+
+/// Stops before the instruction at `addr` runs, when the condition holds if
+/// there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}", self.addr)?;
+        match &self.condition {
+            Some(c) => write!(f, " if {c}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Stops after an instruction that read or wrote `addr`, depending on the
+/// kinds of access watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn hit(&self, addr: u16, access: Access) -> bool {
+        self.addr == addr
+            && match access {
+                Access::Read => self.read,
+                Access::Write => self.write,
+            }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{:04x} {kind}", self.addr)
+    }
+}
+
+/// An access that hit a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub access: Access,
+    pub value: u8,
+}
+
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    // a step finished
+    Step,
+    // the frames asked for ran
+    Frame,
+    // the CPU ran into an opcode it doesn't have, PC is left on it
+    Illegal(CpuError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at {addr:04x}"),
+            Stop::Watchpoint(hit) => {
+                let access = match hit.access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                write!(f, "{access} of {:02x} at {:04x}", hit.value, hit.addr)
+            }
+            Stop::Step => write!(f, "stepped"),
+            Stop::Frame => write!(f, "frame done"),
+            Stop::Illegal(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// What the debugger runs the system for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    Pause,
+    Continue,
+    StepIn,
+    // until the call at the start returns to `ret`, with the stack at `sp`
+    // or above so recursive calls don't count
+    StepOver { ret: u16, sp: u16 },
+    // until a return pops the stack above `sp`
+    StepOut { sp: u16 },
+    Frames(u32),
+}
+
+/// Runs a system while checking breakpoints and watchpoints after every
+/// instruction. The way it runs is picked with `resume`, `step_in`,
+/// `step_over`, `step_out` or `run_frames`, then `run_frame` advances it up to
+/// a frame at a time so a window can keep drawing in between. Watchpoints
+/// live in `Memory`, which sees every access.
+#[derive(Debug)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    goal: Goal,
+    // opcode of the instruction about to run, to tell when a step out returns
+    opcode: u8,
+}
+
+impl Debugger {
+    /// Starts paused.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            goal: Goal::Pause,
+            opcode: 0,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.goal == Goal::Pause
+    }
+
+    pub fn pause(&mut self) {
+        self.goal = Goal::Pause;
+    }
+
+    /// Run until a breakpoint or watchpoint is hit.
+    pub fn resume(&mut self) {
+        self.goal = Goal::Continue;
+    }
+
+    /// Run a single instruction.
+    pub fn step_in(&mut self) {
+        self.goal = Goal::StepIn;
+    }
+
+    /// Run a single instruction, or a whole call when it's a `call` or `rst`.
+    pub fn step_over(&mut self, system: &mut System) {
+        let r = &system.cpu.registers;
+        let (pc, sp) = (r.pc, r.sp);
+        self.goal = match system.cpu.memory.peek(pc) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => Goal::StepOver {
+                ret: pc.wrapping_add(3),
+                sp,
+            },
+            op if op & 0xC7 == 0xC7 => Goal::StepOver {
+                ret: pc.wrapping_add(1),
+                sp,
+            },
+            _ => Goal::StepIn,
+        };
+    }
+
+    /// Run until the current function returns.
+    pub fn step_out(&mut self, system: &mut System) {
+        self.opcode = system.cpu.memory.peek(system.cpu.registers.pc);
+        self.goal = Goal::StepOut {
+            sp: system.cpu.registers.sp,
+        };
+    }
+
+    /// Run the given number of frames.
+    pub fn run_frames(&mut self, frames: u32) {
+        self.goal = match frames {
+            0 => Goal::Pause,
+            n => Goal::Frames(n),
+        };
+    }
+
+    /// Run the system up to the end of the frame, returning why it stopped
+    /// early or once the frames asked for are done. Does nothing while
+    /// paused, and pauses whenever it stops.
+    pub fn run_frame(&mut self, system: &mut System) -> Option<Stop> {
+        if self.paused() {
+            return None;
+        }
+        let mut stop = None;
+        let finished = system
+            .run_frame_until(|s| {
+                stop = self.check(s);
+                stop.is_some()
+            })
+            .unwrap_or_else(|e| {
+                stop = Some(Stop::Illegal(e));
+                false
+            });
+        if finished
+            && stop.is_none()
+            && let Goal::Frames(n) = &mut self.goal
+        {
+            *n -= 1;
+            if *n == 0 {
+                stop = Some(Stop::Frame);
+            }
+        }
+        if stop.is_some() {
+            self.goal = Goal::Pause;
+        }
+        stop
+    }
+
+    /// Run until stopped, which may be never when nothing is set to stop at.
+    pub fn run(&mut self, system: &mut System) -> Stop {
+        loop {
+            if let Some(stop) = self.run_frame(system) {
+                return stop;
+            }
+        }
+    }
+
+    /// Whether to stop after the instruction that just ran.
+    fn check(&mut self, system: &mut System) -> Option<Stop> {
+        if let Some(hit) = system.cpu.memory.take_watch_hit() {
+            return Some(Stop::Watchpoint(hit));
+        }
+        let r = &system.cpu.registers;
+        if self
+            .breakpoints
+            .iter()
+            .any(|b| b.addr == r.pc && b.condition.is_none_or(|c| c.holds(r)))
+        {
+            return Some(Stop::Breakpoint(r.pc));
+        }
+        let done = match self.goal {
+            Goal::StepIn => true,
+            Goal::StepOver { ret, sp } => r.pc == ret && r.sp >= sp,
+            Goal::StepOut { sp } => {
+                let returned = matches!(self.opcode, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+                let (pc, above) = (r.pc, r.sp > sp);
+                self.opcode = system.cpu.memory.peek(pc);
+                returned && above
+            }
+            _ => false,
+        };
+        done.then_some(Stop::Step)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BootParameters;
+    use crate::cpu::IllegalOpcodePolicy;
+
+    // C000: call C010
+    // C003: inc a
+    // C004: jr C004
+    // C010: ld a, 42
+    // C012: ld (D000), a
+    // C015: ret
+    fn setup() -> System {
+        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
+        let mut system = System::new(BootParameters::new(&p)).unwrap();
+        system.initialize();
+        let code: [(u16, &[u8]); 2] = [
+            (0xC000, &[0xCD, 0x10, 0xC0, 0x3C, 0x18, 0xFE]),
+            (0xC010, &[0x3E, 0x42, 0xEA, 0x00, 0xD0, 0xC9]),
+        ];
+        for (addr, bytes) in code {
+            for (i, b) in bytes.iter().enumerate() {
+                system.cpu.memory.write_byte(addr + i as u16, *b);
+            }
+        }
+        system.cpu.registers.pc = 0xC000;
+        system
+    }
+
+    #[test]
+    fn condition_test() {
+        let c: Condition = "hl >= $c000".parse().unwrap();
+        assert_eq!(c.register, Register::HL);
+        assert_eq!(c.comparison, Comparison::Ge);
+        assert_eq!(c.value, 0xC000);
+        assert_eq!("a==42".parse::<Condition>().unwrap().value, 0x42);
+        assert_eq!(
+            "x == 1".parse::<Condition>(),
+            Err(DebugError::Register("x".to_owned()))
+        );
+        assert!("a = 1".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn step_test() {
+        let mut system = setup();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_frame(&mut system), None);
+
+        debugger.step_in();
+        assert_eq!(debugger.run(&mut system), Stop::Step);
+        assert_eq!(system.cpu.registers.pc, 0xC010);
+        debugger.step_over(&mut system);
+        assert_eq!(debugger.run(&mut system), Stop::Step);
+        assert_eq!(system.cpu.registers.pc, 0xC012);
+        debugger.step_out(&mut system);
+        assert_eq!(debugger.run(&mut system), Stop::Step);
+        assert_eq!(system.cpu.registers.pc, 0xC003);
+
+        let mut system = setup();
+        debugger.step_over(&mut system);
+        assert_eq!(debugger.run(&mut system), Stop::Step);
+        assert_eq!(system.cpu.registers.pc, 0xC003);
+        assert_eq!(system.cpu.registers.acc, 0x42);
+        assert!(debugger.paused());
+
+        debugger.run_frames(2);
+        assert_eq!(debugger.run_frame(&mut system), None);
+        assert_eq!(debugger.run_frame(&mut system), Some(Stop::Frame));
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let mut system = setup();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint {
+            addr: 0xC003,
+            condition: Some("a != 42".parse().unwrap()),
+        });
+        debugger.breakpoints.push(Breakpoint {
+            addr: 0xC012,
+            condition: None,
+        });
+        debugger.resume();
+        assert_eq!(debugger.run(&mut system), Stop::Breakpoint(0xC012));
+        assert_eq!(system.cpu.registers.pc, 0xC012);
+
+        // resuming runs the instruction the breakpoint is on, a holds 42 when
+        // returning so the conditional one doesn't stop
+        system.cpu.memory.watchpoints.push(Watchpoint {
+            addr: 0xD000,
+            read: false,
+            write: true,
+        });
+        debugger.resume();
+        let hit = WatchHit {
+            addr: 0xD000,
+            access: Access::Write,
+            value: 0x42,
+        };
+        assert_eq!(debugger.run(&mut system), Stop::Watchpoint(hit));
+        assert_eq!(system.cpu.registers.pc, 0xC015);
+
+        debugger.run_frames(1);
+        assert_eq!(debugger.run_frame(&mut system), Some(Stop::Frame));
+    }
+
+    #[test]
+    fn watch_registers_test() {
+        let mut system = setup();
+        system.cpu.memory.write_byte(0xC000, 0x00);
+        // nothing is pending, but enabled interrupts are checked for
+        system.cpu.ime = true;
+        // the interrupt logic and the PPU reading these aren't CPU accesses
+        for addr in [0xFF0F, 0xFFFF, 0xFF40] {
+            system.cpu.memory.watchpoints.push(Watchpoint {
+                addr,
+                read: true,
+                write: false,
+            });
+        }
+        let mut debugger = Debugger::new();
+        debugger.step_in();
+        assert_eq!(debugger.run(&mut system), Stop::Step);
+        assert_eq!(system.cpu.registers.pc, 0xC001);
+    }
+
+    #[test]
+    fn illegal_test() {
+        let mut system = setup();
+        system.cpu.illegal_policy = IllegalOpcodePolicy::Break;
+        system.cpu.memory.write_byte(0xC015, 0xFD);
+        let mut debugger = Debugger::new();
+        debugger.resume();
+        let error = CpuError::IllegalOpcode {
+            opcode: 0xFD,
+            addr: 0xC015,
+        };
+        assert_eq!(debugger.run(&mut system), Stop::Illegal(error));
+        assert_eq!(system.cpu.registers.pc, 0xC015);
+        assert!(debugger.paused());
+        assert_eq!(
+            Stop::Illegal(error).to_string(),
+            "illegal opcode fd at c015"
+        );
+    }
+}
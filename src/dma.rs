@@ -0,0 +1,213 @@
+use crate::ram::MemoryRegister;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use std::ops::Range;
+
+// bytes copied by OAM DMA
+pub const OAM_SIZE: u8 = 0xA0;
+// bytes copied by VRAM DMA at a time
+pub const BLOCK_SIZE: u16 = 0x10;
+// T-cycles the CPU is stopped for while a VRAM DMA block is copied
+pub const BLOCK_CYCLES: u32 = 32;
+
+/// OAM DMA, started by writing the upper byte of the source address to DMA
+/// (0xFF46). After a machine cycle of setup it copies 160 bytes to OAM, one
+/// per machine cycle, and in the meantime the CPU can only use the I/O
+/// registers and high RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamDma {
+    pub source: u16,
+    // CPU T-cycles since the transfer started
+    cycles: u32,
+    // bytes copied so far
+    copied: u8,
+}
+
+impl OamDma {
+    pub fn new(val: u8) -> OamDma {
+        OamDma {
+            source: (val as u16) << 8,
+            cycles: 0,
+            copied: 0,
+        }
+    }
+
+    /// Advance by the given CPU T-cycles and return the offsets of the bytes
+    /// due to be copied.
+    pub fn tick(&mut self, cycles: u32) -> Range<u8> {
+        self.cycles += cycles;
+        let due = (self.cycles / 4).saturating_sub(1).min(OAM_SIZE as u32) as u8;
+        let range = self.copied..due;
+        self.copied = due;
+        range
+    }
+
+    pub fn done(&self) -> bool {
+        self.copied == OAM_SIZE
+    }
+
+    /// Whether the CPU is locked out of the bus, which isn't the case during
+    /// setup.
+    pub fn blocking(&self) -> bool {
+        self.cycles >= 4
+    }
+}
+
+impl Snapshot for OamDma {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.source);
+        w.u32(self.cycles);
+        w.u8(self.copied);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.source = r.u16()?;
+        self.cycles = r.u32()?;
+        self.copied = r.u8()?;
+        Ok(())
+    }
+}
+
+/// CGB VRAM DMA, copying from ROM or RAM to the selected VRAM bank in blocks
+/// of 16 bytes:
+///   * HDMA1, HDMA2 - source, the lower 4 bits are ignored
+///   * HDMA3, HDMA4 - destination within VRAM, only bits 4-12 are used
+///   * HDMA5 - writing starts a transfer of (bits 0-6 + 1) blocks
+///
+/// With bit 7 of HDMA5 clear the whole transfer happens at once (general
+/// purpose DMA), with it set one block is copied every HBlank. Writing HDMA5
+/// with bit 7 clear during an HBlank transfer cancels it instead. Reading
+/// HDMA5 gives the blocks left minus one, with bit 7 set once nothing is
+/// being transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hdma {
+    pub source: u16,
+    pub dest: u16,
+    // blocks left of the current transfer
+    blocks: u8,
+    // an HBlank transfer is in progress
+    pub hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            dest: 0,
+            blocks: 0,
+            hblank: false,
+        }
+    }
+
+    /// Write one of HDMA1-HDMA5, returns the blocks to copy right away for a
+    /// general purpose transfer.
+    pub fn write(&mut self, addr: u16, val: u8) -> u8 {
+        match addr {
+            a if a == MemoryRegister::HDMA1 as u16 => {
+                self.source = (self.source & 0x00FF) | (val as u16) << 8;
+            }
+            a if a == MemoryRegister::HDMA2 as u16 => {
+                self.source = (self.source & 0xFF00) | (val & 0xF0) as u16;
+            }
+            a if a == MemoryRegister::HDMA3 as u16 => {
+                self.dest = (self.dest & 0x00FF) | ((val & 0x1F) as u16) << 8;
+            }
+            a if a == MemoryRegister::HDMA4 as u16 => {
+                self.dest = (self.dest & 0xFF00) | (val & 0xF0) as u16;
+            }
+            _ if self.hblank && val & 0x80 == 0 => self.hblank = false,
+            _ => {
+                self.blocks = (val & 0x7F) + 1;
+                self.hblank = val & 0x80 != 0;
+                if !self.hblank {
+                    return self.blocks;
+                }
+            }
+        }
+        0
+    }
+
+    pub fn read_status(&self) -> u8 {
+        let left = self.blocks.wrapping_sub(1) & 0x7F;
+        if self.hblank { left } else { 0x80 | left }
+    }
+
+    /// Addresses of the next block, moving the transfer along. Returns the
+    /// source and the offset within the VRAM bank.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.dest & 0x1FF0);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.dest = self.dest.wrapping_add(BLOCK_SIZE) & 0x1FF0;
+        self.blocks -= 1;
+        if self.blocks == 0 {
+            self.hblank = false;
+        }
+        block
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Hdma::new()
+    }
+}
+
+impl Snapshot for Hdma {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.source);
+        w.u16(self.dest);
+        w.u8(self.blocks);
+        w.bool(self.hblank);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.source = r.u16()?;
+        self.dest = r.u16()?;
+        self.blocks = r.u8()?;
+        self.hblank = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oam_dma_test() {
+        let mut dma = OamDma::new(0xC1);
+        assert_eq!(dma.source, 0xC100);
+        // nothing is copied during setup
+        assert_eq!(dma.tick(4), 0..0);
+        assert!(dma.blocking());
+        assert_eq!(dma.tick(8), 0..2);
+        assert_eq!(dma.tick(1000), 2..OAM_SIZE);
+        assert!(dma.done());
+    }
+
+    #[test]
+    fn hdma_test() {
+        let mut hdma = Hdma::new();
+        hdma.write(MemoryRegister::HDMA1 as u16, 0x12);
+        hdma.write(MemoryRegister::HDMA2 as u16, 0x3F);
+        hdma.write(MemoryRegister::HDMA3 as u16, 0xFF);
+        hdma.write(MemoryRegister::HDMA4 as u16, 0xFF);
+        assert_eq!((hdma.source, hdma.dest), (0x1230, 0x1FF0));
+        assert_eq!(hdma.read_status(), 0xFF);
+
+        assert_eq!(hdma.write(MemoryRegister::HDMA5 as u16, 0x01), 2);
+        assert_eq!(hdma.next_block(), (0x1230, 0x1FF0));
+        // the destination wraps within VRAM
+        assert_eq!(hdma.next_block(), (0x1240, 0x0000));
+        assert_eq!(hdma.read_status(), 0xFF);
+
+        // HBlank transfers can be cancelled
+        assert_eq!(hdma.write(MemoryRegister::HDMA5 as u16, 0x82), 0);
+        assert!(hdma.hblank);
+        assert_eq!(hdma.read_status(), 0x02);
+        hdma.next_block();
+        assert_eq!(hdma.read_status(), 0x01);
+        hdma.write(MemoryRegister::HDMA5 as u16, 0x00);
+        assert!(!hdma.hblank);
+        assert_eq!(hdma.read_status(), 0x81);
+    }
+}
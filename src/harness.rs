@@ -0,0 +1,143 @@
+use crate::{
+    BootParameters, Hardware,
+    cpu::IllegalOpcodePolicy,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    serial::Capture,
+    system::System,
+};
+use std::path::Path;
+
+// blargg's ROMs mark cartridge RAM with this signature once they start
+// reporting through it
+const RAM_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+// status byte value while the test is still running
+const RAM_RUNNING: u8 = 0x80;
+
+/// Result of running a test ROM, holding whatever text it reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed(String),
+    Failed(String),
+    // the ROM didn't finish within the frame budget
+    Timeout(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed(_))
+    }
+}
+
+/// Runs test ROMs without a window until they report a result. Blargg's
+/// ROMs report over the serial port and most of them also write their output
+/// to cartridge RAM at 0xA004, preceded by a status byte at 0xA000 and a
+/// signature at 0xA001. All of them print to the screen, and some like
+/// `halt_bug.gb` only do that, using a font where the tile numbers are ASCII.
+#[derive(Debug)]
+pub struct Harness {
+    pub system: System,
+    // plugged into the serial port to keep what the ROM sends
+    serial: Capture,
+    // frames to run before giving up
+    budget: u32,
+}
+
+impl Harness {
+    /// Panics when the ROM can't be loaded, test ROMs are expected to be valid.
+    pub fn new(rom: &Path, model: Hardware, budget: u32) -> Harness {
+        let params = BootParameters {
+            model: Some(model),
+            ..BootParameters::new(rom)
+        };
+        let mut system = System::new(params).unwrap_or_else(|e| panic!("{}: {e}", rom.display()));
+        system.initialize();
+        // fail right away instead of spending the budget locked up
+        system.cpu.illegal_policy = IllegalOpcodePolicy::Error;
+        let serial = Capture::new();
+        system.cpu.memory.serial.link = Box::new(serial.clone());
+        Harness {
+            system,
+            serial,
+            budget,
+        }
+    }
+
+    /// Run frames until the ROM reports it passed or failed, or the budget is
+    /// spent. Running into an illegal opcode fails it.
+    pub fn run(&mut self) -> Outcome {
+        for _ in 0..self.budget {
+            if let Err(e) = self.system.run_frame() {
+                return Outcome::Failed(format!("{}\n{e}", self.output()));
+            }
+            if let Some(outcome) = self.outcome() {
+                // let the rest of the message come through
+                self.system.run_frame().ok();
+                return self.outcome().unwrap_or(outcome);
+            }
+        }
+        Outcome::Timeout(self.output())
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        if let Some((status, text)) = self.ram_output() {
+            return match status {
+                RAM_RUNNING => None,
+                0 => Some(Outcome::Passed(text)),
+                _ => Some(Outcome::Failed(text)),
+            };
+        }
+        [self.serial_output(), self.screen_output()]
+            .into_iter()
+            .find_map(|text| {
+                if text.contains("Passed") {
+                    Some(Outcome::Passed(text))
+                } else if text.contains("Failed") {
+                    Some(Outcome::Failed(text))
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Text reported so far, preferring cartridge RAM and then the serial
+    /// port over the screen.
+    pub fn output(&self) -> String {
+        self.ram_output()
+            .map(|(_, text)| text)
+            .or_else(|| Some(self.serial_output()).filter(|text| !text.is_empty()))
+            .unwrap_or_else(|| self.screen_output())
+    }
+
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(&self.serial.bytes()).into_owned()
+    }
+
+    /// Visible part of the background tile map read as text, one line per
+    /// row of tiles.
+    pub fn screen_output(&self) -> String {
+        let vram = &self.system.cpu.memory.vram;
+        (0..SCREEN_HEIGHT / 8)
+            .map(|row| {
+                let start = 0x1800 + row * 32;
+                let line: String = vram[start..start + SCREEN_WIDTH / 8]
+                    .iter()
+                    .map(|&t| if t.is_ascii_graphic() { t as char } else { ' ' })
+                    .collect();
+                line.trim_end().to_owned()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_owned()
+    }
+
+    /// Status and text written to cartridge RAM, if the signature is there.
+    fn ram_output(&self) -> Option<(u8, String)> {
+        let ram = &self.system.cpu.memory.cartridge.as_ref()?.ram;
+        if ram.get(1..4)? != RAM_SIGNATURE {
+            return None;
+        }
+        let text = ram[4..].split(|&b| b == 0).next().unwrap_or_default();
+        Some((ram[0], String::from_utf8_lossy(text).into_owned()))
+    }
+}
@@ -1 +1,5 @@
+pub mod cli;
+pub mod input;
+pub mod repl;
+pub mod screenshot;
 pub mod window;
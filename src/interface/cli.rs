@@ -0,0 +1,446 @@
+use crate::debugger::parse_number;
+use crate::{BootParameters, Hardware, cpu::IllegalOpcodePolicy, joypad::Button};
+use eframe::egui::Key;
+use std::fmt;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: they [OPTIONS] <ROM>
+       they disasm [--from <ADDR>] [--count <N>] <ROM>
+       they trace-diff [OPTIONS] [--context <N>] <ROM> <LOG>
+
+Options:
+  --headless           run without a window
+  --frames <N>         stop after N frames
+  --scale <N>          initial window scale [default: 3]
+  --boot-rom <FILE>    run a boot ROM before the cartridge
+  --model <MODEL>      dmg0, dmg, mgb or cgb [default: cgb for CGB ROMs,
+                       dmg otherwise]
+  --save-dir <DIR>     keep .sav files and save states in DIR
+  --trace <FILE>       log the CPU state before every instruction to FILE
+  --screenshot <FILE>  write the last frame to a PNG file when stopping
+  --bind <KEY>=<BUTTON>
+                       press a button with a key instead of the defaults,
+                       the buttons are up, down, left, right, a, b, start,
+                       select and none to unbind the key
+  --debug              start paused in the debugger, a REPL with --headless
+  --illegal <POLICY>   on an illegal opcode lock up the CPU, break into the
+                       debugger or stop with an error: lock, break or error
+                       [default: lock]
+  -h, --help           print this help
+
+disasm lists the instructions in the first 32 KiB of a ROM:
+  --from <ADDR>        hexadecimal address to start at [default: 0x100]
+  --count <N>          instructions to list [default: 32]
+
+trace-diff runs a ROM against a reference log in the --trace format, which
+may be gzipped, and shows where they first differ, on a DMG unless --model
+says otherwise:
+  --context <N>        matching lines to show before that [default: 10]
+";
+
+/// What the command line asked for.
+#[derive(Debug)]
+pub enum Command {
+    Run(BootParameters),
+    Disassemble {
+        rom: PathBuf,
+        from: u16,
+        count: u32,
+    },
+    TraceDiff {
+        params: BootParameters,
+        log: PathBuf,
+        context: usize,
+    },
+    Help,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    UnknownOption(String),
+    // an option that takes a value was the last argument
+    MissingValue(String),
+    InvalidValue { option: String, value: String },
+    MissingRom,
+    MissingLog,
+    // more than one ROM
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::UnknownOption(o) => write!(f, "unknown option {o}"),
+            CliError::MissingValue(o) => write!(f, "{o} needs a value"),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "invalid value {value:?} for {option}")
+            }
+            CliError::MissingRom => write!(f, "no ROM given"),
+            CliError::MissingLog => write!(f, "no reference log given"),
+            CliError::UnexpectedArgument(a) => write!(f, "unexpected argument {a}"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parse the arguments following the program name. Options take their value
+/// either as the next argument or after an `=`, `--frames 60` and
+/// `--frames=60` are the same.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, CliError> {
+    let mut args = Args {
+        args: args.into_iter().peekable(),
+        inline: None,
+    };
+    if args.args.next_if(|a| a == "disasm").is_some() {
+        return parse_disasm(args);
+    }
+    // takes the same options as running a ROM
+    let trace_diff = args.args.next_if(|a| a == "trace-diff").is_some();
+    let mut params = BootParameters::default();
+    let (mut rom, mut log, mut context) = (None, None, 10);
+    while let Some(arg) = args.next_arg()? {
+        let option = match arg {
+            Arg::Positional(arg) => {
+                match rom {
+                    Some(_) if trace_diff && log.is_none() => log = Some(arg.into()),
+                    _ => rom_argument(&mut rom, arg)?,
+                }
+                continue;
+            }
+            Arg::Option(option) => option,
+        };
+        match option.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--headless" => params.headless = true,
+            "--debug" => params.debug = true,
+            "--frames" => params.frames = Some(parsed(&option, args.value(&option)?, |_| true)?),
+            "--scale" => {
+                params.scale = parsed(&option, args.value(&option)?, |s: &f32| {
+                    *s > 0.0 && s.is_finite()
+                })?
+            }
+            "--boot-rom" => params.boot_rom = Some(args.value(&option)?.into()),
+            "--model" => {
+                let value = args.value(&option)?;
+                params.model = Some(match value.to_ascii_lowercase().as_str() {
+                    "dmg0" => Hardware::Dmg0,
+                    "dmg" => Hardware::Dmg,
+                    "mgb" => Hardware::Mgb,
+                    "cgb" => Hardware::Cgb,
+                    _ => return Err(invalid(&option, value)),
+                })
+            }
+            "--illegal" => {
+                let value = args.value(&option)?;
+                params.illegal = match value.to_ascii_lowercase().as_str() {
+                    "lock" => IllegalOpcodePolicy::Lock,
+                    "break" => IllegalOpcodePolicy::Break,
+                    "error" => IllegalOpcodePolicy::Error,
+                    _ => return Err(invalid(&option, value)),
+                }
+            }
+            "--save-dir" => params.save_dir = Some(args.value(&option)?.into()),
+            "--trace" => params.trace = Some(args.value(&option)?.into()),
+            "--screenshot" => params.screenshot = Some(args.value(&option)?.into()),
+            "--bind" => {
+                let value = args.value(&option)?;
+                let binding = value.split_once('=').and_then(|(key, button)| {
+                    let button = match button {
+                        "none" => None,
+                        b => Some(Button::from_name(b)?),
+                    };
+                    Some((Key::from_name(key)?, button))
+                });
+                match binding {
+                    Some(binding) => params.bindings.push(binding),
+                    None => return Err(invalid(&option, value)),
+                }
+            }
+            "--context" if trace_diff => context = parsed(&option, args.value(&option)?, |_| true)?,
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+    params.rom_path = rom.ok_or(CliError::MissingRom)?;
+    if trace_diff {
+        params.headless = true;
+        // reference logs come from a DMG unless the model says otherwise
+        params.model.get_or_insert(Hardware::Dmg);
+        return Ok(Command::TraceDiff {
+            params,
+            log: log.ok_or(CliError::MissingLog)?,
+            context,
+        });
+    }
+    Ok(Command::Run(params))
+}
+
+fn parse_disasm<I: Iterator<Item = String>>(
+    mut args: Args<Peekable<I>>,
+) -> Result<Command, CliError> {
+    let (mut rom, mut from, mut count) = (None, 0x100, 32);
+    while let Some(arg) = args.next_arg()? {
+        let option = match arg {
+            Arg::Positional(arg) => {
+                rom_argument(&mut rom, arg)?;
+                continue;
+            }
+            Arg::Option(option) => option,
+        };
+        match option.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--from" => {
+                let value = args.value(&option)?;
+                from = parse_number(&value).map_err(|_| invalid(&option, value))?;
+            }
+            "--count" => count = parsed(&option, args.value(&option)?, |_| true)?,
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+    Ok(Command::Disassemble {
+        rom: rom.ok_or(CliError::MissingRom)?,
+        from,
+        count,
+    })
+}
+
+/// Take the ROM, there's only one.
+fn rom_argument(rom: &mut Option<PathBuf>, arg: String) -> Result<(), CliError> {
+    match rom.replace(PathBuf::from(&arg)) {
+        Some(_) => Err(CliError::UnexpectedArgument(arg)),
+        None => Ok(()),
+    }
+}
+
+enum Arg {
+    Option(String),
+    Positional(String),
+}
+
+/// Splits the arguments into options and the rest, keeping the value of an
+/// `--option=value` around for `value`.
+struct Args<I> {
+    args: I,
+    inline: Option<String>,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    fn next_arg(&mut self) -> Result<Option<Arg>, CliError> {
+        if let Some(value) = self.inline.take() {
+            return Err(CliError::UnexpectedArgument(value));
+        }
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        if !arg.starts_with('-') || arg == "-" {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+        Ok(Some(Arg::Option(match arg.split_once('=') {
+            Some((option, value)) => {
+                self.inline = Some(value.to_owned());
+                option.to_owned()
+            }
+            None => arg,
+        })))
+    }
+
+    /// The value of the option just taken.
+    fn value(&mut self, option: &str) -> Result<String, CliError> {
+        self.inline
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| CliError::MissingValue(option.to_owned()))
+    }
+}
+
+/// Parse an option's value, which also has to pass `valid`.
+fn parsed<T: FromStr>(option: &str, value: String, valid: fn(&T) -> bool) -> Result<T, CliError> {
+    match value.parse() {
+        Ok(v) if valid(&v) => Ok(v),
+        _ => Err(invalid(option, value)),
+    }
+}
+
+fn invalid(option: &str, value: String) -> CliError {
+    CliError::InvalidValue {
+        option: option.to_owned(),
+        value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<BootParameters, CliError> {
+        match parse(args.iter().map(|a| a.to_string()))? {
+            Command::Run(params) => Ok(params),
+            _ => panic!("expected parameters"),
+        }
+    }
+
+    #[test]
+    fn options_test() {
+        let p = run(&["game.gb"]).unwrap();
+        assert_eq!(p.rom_path, PathBuf::from("game.gb"));
+        assert!(!p.headless);
+        assert_eq!(p.frames, None);
+        assert_eq!(p.model, None);
+        assert_eq!(p.illegal, IllegalOpcodePolicy::Lock);
+
+        let p = run(&[
+            "--headless",
+            "--frames=600",
+            "--scale",
+            "2",
+            "--model",
+            "CGB",
+            "--boot-rom",
+            "cgb_boot.bin",
+            "--save-dir",
+            "saves",
+            "--trace",
+            "trace.log",
+            "game.gb",
+            "--screenshot",
+            "last.png",
+            "--bind",
+            "W=up",
+            "--bind=Space=A",
+            "--bind",
+            "Z=none",
+            "--debug",
+            "--illegal=break",
+        ])
+        .unwrap();
+        assert!(p.headless);
+        assert_eq!(p.frames, Some(600));
+        assert_eq!(p.scale, 2.0);
+        assert_eq!(p.model, Some(Hardware::Cgb));
+        assert_eq!(p.boot_rom, Some(PathBuf::from("cgb_boot.bin")));
+        assert_eq!(p.save_path("sav"), PathBuf::from("saves/game.sav"));
+        assert_eq!(p.trace, Some(PathBuf::from("trace.log")));
+        assert_eq!(p.screenshot, Some(PathBuf::from("last.png")));
+        assert_eq!(
+            p.bindings,
+            [
+                (Key::W, Some(Button::Up)),
+                (Key::Space, Some(Button::ButtonA)),
+                (Key::Z, None),
+            ]
+        );
+        assert!(p.debug);
+        assert_eq!(p.illegal, IllegalOpcodePolicy::Break);
+    }
+
+    #[test]
+    fn disasm_test() {
+        let args = ["disasm", "--from=$150", "--count", "10", "game.gb"];
+        assert!(matches!(
+            parse(args.iter().map(|a| a.to_string())),
+            Ok(Command::Disassemble {
+                from: 0x150,
+                count: 10,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse(["disasm".to_owned(), "game.gb".to_owned()]),
+            Ok(Command::Disassemble {
+                from: 0x100,
+                count: 32,
+                ..
+            })
+        ));
+        // disasm only means the command when it comes first
+        assert_eq!(
+            run(&["game.gb", "disasm"]).unwrap_err(),
+            CliError::UnexpectedArgument("disasm".to_owned())
+        );
+        assert!(matches!(
+            parse(["disasm".to_owned(), "--frames=1".to_owned()]),
+            Err(CliError::UnknownOption(_))
+        ));
+    }
+
+    #[test]
+    fn trace_diff_test() {
+        let args = [
+            "trace-diff",
+            "--model=mgb",
+            "game.gb",
+            "golden.log.gz",
+            "--context",
+            "3",
+        ];
+        match parse(args.iter().map(|a| a.to_string())) {
+            Ok(Command::TraceDiff {
+                params,
+                log,
+                context,
+            }) => {
+                assert_eq!(params.rom_path, PathBuf::from("game.gb"));
+                assert_eq!(params.model, Some(Hardware::Mgb));
+                assert_eq!(log, PathBuf::from("golden.log.gz"));
+                assert_eq!(context, 3);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        let args = ["trace-diff", "game.gb", "golden.log"];
+        match parse(args.iter().map(|a| a.to_string())) {
+            Ok(Command::TraceDiff { params, .. }) => {
+                assert_eq!(params.model, Some(Hardware::Dmg))
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(
+            parse(["trace-diff".to_owned(), "game.gb".to_owned()]).unwrap_err(),
+            CliError::MissingLog
+        );
+        // only a trace diff takes a log
+        assert_eq!(
+            run(&["game.gb", "golden.log"]).unwrap_err(),
+            CliError::UnexpectedArgument("golden.log".to_owned())
+        );
+        assert_eq!(
+            run(&["game.gb", "--context=3"]).unwrap_err(),
+            CliError::UnknownOption("--context".to_owned())
+        );
+    }
+
+    #[test]
+    fn errors_test() {
+        assert_eq!(run(&[]).unwrap_err(), CliError::MissingRom);
+        assert_eq!(
+            run(&["a.gb", "b.gb"]).unwrap_err(),
+            CliError::UnexpectedArgument("b.gb".to_owned())
+        );
+        assert_eq!(
+            run(&["a.gb", "--frames"]).unwrap_err(),
+            CliError::MissingValue("--frames".to_owned())
+        );
+        assert!(matches!(
+            run(&["a.gb", "--model", "gba"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            run(&["a.gb", "--scale=0"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            run(&["a.gb", "--bind", "W=turbo"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert_eq!(
+            run(&["a.gb", "--fast"]).unwrap_err(),
+            CliError::UnknownOption("--fast".to_owned())
+        );
+        assert!(matches!(
+            parse(["game.gb".to_owned(), "-h".to_owned()]),
+            Ok(Command::Help)
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::joypad::Button;
+use eframe::egui::{Event, InputState, Key};
+use std::collections::HashMap;
+
+/// Keyboard keys mapped to the joypad buttons they press.
+#[derive(Debug)]
+pub struct Bindings {
+    keys: HashMap<Key, Button>,
+}
+
+impl Bindings {
+    /// Arrow keys for the direction pad, X and Z for A and B, Enter for
+    /// start and Backspace for select.
+    pub fn new() -> Bindings {
+        let mut b = Bindings {
+            keys: HashMap::new(),
+        };
+        b.bind(Key::ArrowUp, Button::Up);
+        b.bind(Key::ArrowDown, Button::Down);
+        b.bind(Key::ArrowLeft, Button::Left);
+        b.bind(Key::ArrowRight, Button::Right);
+        b.bind(Key::X, Button::ButtonA);
+        b.bind(Key::Z, Button::ButtonB);
+        b.bind(Key::Enter, Button::Start);
+        b.bind(Key::Backspace, Button::Select);
+        b
+    }
+
+    /// Map a key to a button, replacing whatever the key was bound to before.
+    /// A button can be bound to any number of keys.
+    pub fn bind(&mut self, key: Key, button: Button) {
+        self.keys.insert(key, button);
+    }
+
+    pub fn unbind(&mut self, key: Key) {
+        self.keys.remove(&key);
+    }
+
+    pub fn get(&self, key: Key) -> Option<Button> {
+        self.keys.get(&key).copied()
+    }
+
+    /// Button changes caused by the key events of the current frame, auto
+    /// repeated key presses are skipped.
+    pub fn buttons(&self, input: &InputState) -> Vec<(Button, bool)> {
+        input
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Key {
+                    key,
+                    pressed,
+                    repeat: false,
+                    ..
+                } => self.get(*key).map(|button| (button, *pressed)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings::new()
+    }
+}
@@ -0,0 +1,290 @@
+use crate::cpu::disassembler::disassemble;
+use crate::debugger::{Breakpoint, DebugError, Debugger, Watchpoint, parse_number};
+use crate::system::System;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+pub const HELP: &str = "\
+Commands, numbers are hexadecimal:
+  s, step                    run one instruction
+  n, next                    run one instruction, or a whole call
+  o, out                     run until the current function returns
+  c, continue                run until a breakpoint or watchpoint
+  f, frame [N]               run N frames [default: 1]
+  b, break <ADDR> [if <REG> <OP> <VALUE>]
+                             stop before ADDR runs, OP is one of
+                             == != < <= > >=
+  w, watch <ADDR> [r|w|rw]   stop after ADDR is accessed [default: w]
+  d, delete <ADDR>           remove the breakpoints and watchpoints at ADDR
+  l, list                    show breakpoints and watchpoints
+  r, regs                    show the registers
+  x <ADDR> [LEN]             show LEN bytes of memory [default: 10]
+  u, disasm [ADDR] [N]       show N instructions from ADDR [default: pc, 8]
+  h, help                    print this help
+  q, quit                    stop the emulator
+";
+
+/// A line typed into the debugger.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Step,
+    Next,
+    Out,
+    Continue,
+    Frames(u32),
+    Break(Breakpoint),
+    Watch(Watchpoint),
+    Delete(u16),
+    List,
+    Registers,
+    Examine { addr: u16, len: u16 },
+    // from PC without an address
+    Disassemble { addr: Option<u16>, count: u16 },
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let mut arg = |usage| words.next().ok_or(DebugError::Usage(usage));
+        Ok(match name {
+            "s" | "step" => Command::Step,
+            "n" | "next" => Command::Next,
+            "o" | "out" => Command::Out,
+            "c" | "continue" => Command::Continue,
+            "f" | "frame" => match arg("frame [N]") {
+                Ok(n) => Command::Frames(parse_number(n)? as u32),
+                Err(_) => Command::Frames(1),
+            },
+            "b" | "break" => {
+                let addr = parse_number(arg("break <ADDR> [if <CONDITION>]")?)?;
+                let condition = match s.split_once(" if ") {
+                    Some((_, condition)) => Some(condition.parse()?),
+                    None => None,
+                };
+                Command::Break(Breakpoint { addr, condition })
+            }
+            "w" | "watch" => {
+                let addr = parse_number(arg("watch <ADDR> [r|w|rw]")?)?;
+                let (read, write) = match arg("") {
+                    Ok("r") => (true, false),
+                    Ok("rw") => (true, true),
+                    Ok("w") | Err(_) => (false, true),
+                    Ok(_) => return Err(DebugError::Usage("watch <ADDR> [r|w|rw]")),
+                };
+                Command::Watch(Watchpoint { addr, read, write })
+            }
+            "d" | "delete" => Command::Delete(parse_number(arg("delete <ADDR>")?)?),
+            "l" | "list" => Command::List,
+            "r" | "regs" => Command::Registers,
+            "x" => {
+                let addr = parse_number(arg("x <ADDR> [LEN]")?)?;
+                let len = match arg("") {
+                    Ok(len) => parse_number(len)?,
+                    Err(_) => 0x10,
+                };
+                Command::Examine { addr, len }
+            }
+            "u" | "disasm" => {
+                let addr = arg("").ok().map(parse_number).transpose()?;
+                let count = match arg("") {
+                    Ok(count) => parse_number(count)?,
+                    Err(_) => 8,
+                };
+                Command::Disassemble { addr, count }
+            }
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(DebugError::UnknownCommand(name.to_owned())),
+        })
+    }
+}
+
+/// Carry out a command and return what to show for it. The ones that run
+/// the system only set the debugger up, it's up to the caller to run it so
+/// a window can keep drawing in the meantime.
+pub fn execute(command: Command, debugger: &mut Debugger, system: &mut System) -> String {
+    let mut out = String::new();
+    match command {
+        Command::Step => debugger.step_in(),
+        Command::Next => debugger.step_over(system),
+        Command::Out => debugger.step_out(system),
+        Command::Continue => debugger.resume(),
+        Command::Frames(n) => debugger.run_frames(n),
+        Command::Break(b) => {
+            debugger.breakpoints.push(b);
+            out = format!("breakpoint at {b}");
+        }
+        Command::Watch(w) => {
+            system.cpu.memory.watchpoints.push(w);
+            out = format!("watchpoint at {w}");
+        }
+        Command::Delete(addr) => {
+            debugger.breakpoints.retain(|b| b.addr != addr);
+            system.cpu.memory.watchpoints.retain(|w| w.addr != addr);
+        }
+        Command::List => {
+            for b in &debugger.breakpoints {
+                writeln!(out, "break {b}").ok();
+            }
+            for w in &system.cpu.memory.watchpoints {
+                writeln!(out, "watch {w}").ok();
+            }
+            out.pop();
+        }
+        Command::Registers => out = location(system),
+        Command::Examine { addr, len } => {
+            for i in 0..len {
+                let a = addr.wrapping_add(i);
+                if i % 16 == 0 {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    write!(out, "{a:04x}:").ok();
+                }
+                write!(out, " {:02x}", system.cpu.memory.peek(a)).ok();
+            }
+        }
+        Command::Disassemble { addr, count } => {
+            out = listing(system, addr.unwrap_or(system.cpu.registers.pc), count).join("\n")
+        }
+        Command::Help => out = HELP.trim_end().to_owned(),
+        Command::Quit => debugger.pause(),
+    }
+    out
+}
+
+/// The registers and the instruction about to run.
+pub fn location(system: &mut System) -> String {
+    let pc = system.cpu.registers.pc;
+    let next = listing(system, pc, 1).remove(0);
+    format!("{}\n{next}", system.cpu.registers)
+}
+
+/// Lines of disassembly for `count` instructions starting at `addr`.
+pub fn listing(system: &mut System, mut addr: u16, count: u16) -> Vec<String> {
+    let memory = &mut system.cpu.memory;
+    (0..count)
+        .map(|_| {
+            let instruction = disassemble(addr, |a| memory.peek(a));
+            let line = instruction.line(addr);
+            addr = addr.wrapping_add(instruction.size());
+            line
+        })
+        .collect()
+}
+
+/// Read commands from `input` until it ends or `quit` is entered. Running
+/// commands block until the debugger stops, `continue` without anything to
+/// stop at runs for good.
+pub fn run<R: BufRead, W: Write>(system: &mut System, input: R, mut output: W) -> io::Result<()> {
+    let mut debugger = Debugger::new();
+    writeln!(output, "{}", location(system))?;
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            match line.parse() {
+                Ok(Command::Quit) => break,
+                Ok(command) => {
+                    let text = execute(command, &mut debugger, system);
+                    if !text.is_empty() {
+                        writeln!(output, "{text}")?;
+                    }
+                    if !debugger.paused() {
+                        let stop = debugger.run(system);
+                        writeln!(output, "{stop}\n{}", location(system))?;
+                    }
+                }
+                Err(e) => writeln!(output, "{e}")?,
+            }
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BootParameters;
+    use crate::debugger::Condition;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!("s".parse(), Ok(Command::Step));
+        assert_eq!("frame".parse(), Ok(Command::Frames(1)));
+        assert_eq!(
+            "u".parse(),
+            Ok(Command::Disassemble {
+                addr: None,
+                count: 8
+            })
+        );
+        assert_eq!("f 10".parse(), Ok(Command::Frames(16)));
+        assert_eq!(
+            "break $0150 if hl != 0".parse(),
+            Ok(Command::Break(Breakpoint {
+                addr: 0x150,
+                condition: Some(Condition::from_str("hl != 0").unwrap()),
+            }))
+        );
+        assert_eq!(
+            "watch ff40 rw".parse(),
+            Ok(Command::Watch(Watchpoint {
+                addr: 0xFF40,
+                read: true,
+                write: true,
+            }))
+        );
+        assert_eq!(
+            "x c000".parse(),
+            Ok(Command::Examine {
+                addr: 0xC000,
+                len: 0x10
+            })
+        );
+        assert_eq!(
+            "b".parse::<Command>(),
+            Err(DebugError::Usage("break <ADDR> [if <CONDITION>]"))
+        );
+        assert_eq!(
+            "jump 100".parse::<Command>(),
+            Err(DebugError::UnknownCommand("jump".to_owned()))
+        );
+        assert_eq!(
+            "b zz".parse::<Command>(),
+            Err(DebugError::Number("zz".to_owned()))
+        );
+    }
+
+    #[test]
+    fn session_test() {
+        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
+        let mut system = System::new(BootParameters::new(&p)).unwrap();
+        system.initialize();
+        let input = "b 101\nl\nc\ns\nx 100 4\nu 100 2\nbogus\nq\ns\n";
+        let mut output = Vec::new();
+        run(&mut system, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("breakpoint at 0101\n"));
+        assert!(output.contains("> break 0101\n"));
+        // the cartridge starts with nop and then jumps to 0x0213
+        assert!(output.contains("pc: 213"));
+        assert!(output.contains("0100: 00 c3 13 02\n"));
+        assert!(output.contains("> 0100: 00        nop\n0101: c3 13 02  jp $0213\n"));
+        // stops show the next instruction
+        assert!(output.contains("pc: 213 sp:fffe\n0213: "));
+        assert!(output.contains("unknown command \"bogus\""));
+        // nothing runs after quitting
+        assert_eq!(system.cpu.registers.pc, 0x213);
+    }
+}
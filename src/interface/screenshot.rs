@@ -0,0 +1,36 @@
+use crate::ppu::{Pixel, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// Write a frame to a PNG file at its native 160x144.
+pub fn save(framebuffer: &[Pixel; SCREEN_WIDTH * SCREEN_HEIGHT], path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let rgb: Vec<u8> = framebuffer.iter().flat_map(|p| p.rgb()).collect();
+    encoder.write_header()?.write_image_data(&rgb)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_test() {
+        let path = std::env::temp_dir().join(format!("they-screenshot-{}.png", std::process::id()));
+        let mut framebuffer = [Pixel::White; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[1] = Pixel::Black;
+        save(&framebuffer, &path).unwrap();
+
+        let decoder = png::Decoder::new(io::BufReader::new(File::open(&path).unwrap()));
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut data).unwrap();
+        assert_eq!(reader.info().width, SCREEN_WIDTH as u32);
+        assert_eq!(&data[..6], &[0xFF, 0xFF, 0xFF, 0, 0, 0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,69 +1,353 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use crate::system::System;
+use super::{
+    input::Bindings,
+    repl::{self, Command},
+    screenshot,
+};
+use crate::{
+    BootParameters,
+    cpu::IllegalOpcodePolicy,
+    debugger::Debugger,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    system::System,
+};
 use eframe::{
     App, Frame,
-    egui::{self, CentralPanel, Context},
+    egui::{
+        self, CentralPanel, ColorImage, Context, Key, ScrollArea, SidePanel, TextEdit,
+        TextureHandle, TextureId, TextureOptions, TopBottomPanel,
+    },
 };
 use egui_file::FileDialog;
-use std::{
-    ffi::OsStr,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
+
+// frames between writes of battery backed RAM, about a second
+const SAVE_INTERVAL: u32 = 60;
+// save state slots to pick from
+const SLOTS: u8 = 9;
+// lines of debugger output kept around
+const LOG_LINES: usize = 200;
+// instructions shown from the PC on
+const DISASSEMBLY_LINES: u16 = 8;
 
-pub fn run(_mb: System) -> eframe::Result {
+pub fn run(system: System) -> eframe::Result {
+    let scale = system.params.scale;
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([320.0, 240.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([
+            SCREEN_WIDTH as f32 * scale,
+            // leave some room for the menu bar
+            SCREEN_HEIGHT as f32 * scale + 32.0,
+        ]),
         ..Default::default()
     };
     eframe::run_native(
         "They: Gameboy Emulator",
         options,
-        Box::new(|_cc| Ok(Box::<TheyApp>::default())),
+        Box::new(|_cc| Ok(Box::new(TheyApp::new(system)))),
     )
 }
 
-#[derive(Default)]
 struct TheyApp {
-    name: String,
-    age: usize,
+    system: System,
+    bindings: Bindings,
+    screen: Option<TextureHandle>,
     opened_file: Option<PathBuf>,
     open_file_dialog: Option<FileDialog>,
+    // frames run since battery backed RAM was last written
+    frames: u32,
+    // save state slot used by the menu
+    slot: u8,
+    // frames run since the window opened, for `--frames`
+    elapsed: u32,
+    // shown next to the screen with `--debug`
+    debug_panel: Option<DebugPanel>,
+}
+
+impl TheyApp {
+    fn new(system: System) -> TheyApp {
+        let mut bindings = Bindings::new();
+        for &(key, button) in &system.params.bindings {
+            match button {
+                Some(button) => bindings.bind(key, button),
+                None => bindings.unbind(key),
+            }
+        }
+        TheyApp {
+            bindings,
+            screen: None,
+            opened_file: None,
+            open_file_dialog: None,
+            frames: 0,
+            slot: 1,
+            elapsed: 0,
+            debug_panel: system.params.debug.then(DebugPanel::new),
+            system,
+        }
+    }
+
+    /// Replace the running system with a freshly powered on one for the ROM,
+    /// a ROM that fails to load leaves the current one running.
+    fn load(&mut self, rom: &Path) {
+        let params = BootParameters {
+            rom_path: rom.to_path_buf(),
+            ..self.system.params.clone()
+        };
+        let mut system = match System::new(params) {
+            Ok(system) => system,
+            Err(e) => {
+                eprintln!("failed to load {}: {e}", rom.display());
+                return;
+            }
+        };
+        self.flush();
+        system.initialize();
+        system.trace = self.system.trace.take();
+        system.cpu.memory.watchpoints = std::mem::take(&mut self.system.cpu.memory.watchpoints);
+        self.system = system;
+        self.opened_file = Some(rom.to_path_buf());
+    }
+
+    /// Persist the cartridge's RAM, failing to doesn't stop the emulator.
+    fn flush(&mut self) {
+        self.frames = 0;
+        if let Err(e) = self.system.flush() {
+            eprintln!("failed to write the save file: {e}");
+        }
+    }
+
+    fn save_slot(&mut self) {
+        if let Err(e) = self.system.save_slot(self.slot) {
+            eprintln!("failed to save slot {}: {e}", self.slot);
+        }
+    }
+
+    fn load_slot(&mut self) {
+        if let Err(e) = self.system.load_slot(self.slot) {
+            eprintln!("failed to load slot {}: {e}", self.slot);
+        }
+    }
+
+    /// Upload the last frame the PPU rendered to the screen texture.
+    fn update_screen(&mut self, ctx: &Context) -> TextureId {
+        let rgb: Vec<u8> = self
+            .system
+            .display
+            .framebuffer()
+            .iter()
+            .flat_map(|p| p.rgb())
+            .collect();
+        let image = ColorImage::from_rgb([SCREEN_WIDTH, SCREEN_HEIGHT], &rgb);
+        // nearest filtering keeps the pixels sharp when scaled up
+        let screen = self.screen.get_or_insert_with(|| {
+            ctx.load_texture("screen", image.clone(), TextureOptions::NEAREST)
+        });
+        screen.set(image, TextureOptions::NEAREST);
+        screen.id()
+    }
 }
 
 impl App for TheyApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
-        CentralPanel::default().show(ctx, |ui| {
-            if (ui.button("Open")).clicked() {
-                // Show only files with the extension "txt".
-                let filter = Box::new({
-                    let ext = Some(OsStr::new("gb"));
-                    move |path: &Path| -> bool { path.extension() == ext }
-                });
-                let mut dialog =
-                    FileDialog::open_file(self.opened_file.clone()).show_files_filter(filter);
-                dialog.open();
-                self.open_file_dialog = Some(dialog);
+        TopBottomPanel::top("menu").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if (ui.button("Open")).clicked() {
+                    // Show only files with the extension "gb" or "gbc".
+                    let filter = Box::new(|path: &Path| -> bool {
+                        path.extension()
+                            .is_some_and(|ext| ext == "gb" || ext == "gbc")
+                    });
+                    let mut dialog =
+                        FileDialog::open_file(self.opened_file.clone()).show_files_filter(filter);
+                    dialog.open();
+                    self.open_file_dialog = Some(dialog);
+                }
+                ui.separator();
+                egui::ComboBox::from_id_salt("slot")
+                    .selected_text(format!("Slot {}", self.slot))
+                    .show_ui(ui, |ui| {
+                        for slot in 1..=SLOTS {
+                            ui.selectable_value(&mut self.slot, slot, format!("Slot {slot}"));
+                        }
+                    });
+                if ui.button("Save State").clicked() {
+                    self.save_slot();
+                }
+                if ui.button("Load State").clicked() {
+                    self.load_slot();
+                }
+            });
+        });
+
+        let mut selected = None;
+        if let Some(dialog) = &mut self.open_file_dialog {
+            if dialog.show(ctx).selected() {
+                selected = dialog.path().map(Path::to_path_buf);
+            }
+        }
+        if let Some(file) = selected {
+            self.open_file_dialog = None;
+            self.load(&file);
+        }
+
+        let mut quit = false;
+        if let Some(panel) = &mut self.debug_panel {
+            quit = panel.show(ctx, &mut self.system);
+        }
+        if quit {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        // the file dialog and the debugger's command line take the keyboard
+        // while they're in use
+        if !ctx.wants_keyboard_input()
+            && !self
+                .open_file_dialog
+                .as_ref()
+                .is_some_and(FileDialog::visible)
+        {
+            for (button, pressed) in ctx.input(|i| self.bindings.buttons(i)) {
+                self.system.cpu.memory.set_button(button, pressed);
             }
+        }
 
-            if let Some(dialog) = &mut self.open_file_dialog {
-                if dialog.show(ctx).selected() {
-                    if let Some(file) = dialog.path() {
-                        self.opened_file = Some(file.to_path_buf());
+        match &mut self.debug_panel {
+            // nothing runs while paused
+            Some(panel) => panel.run_frame(&mut self.system),
+            None => {
+                if let Err(e) = self.system.run_frame() {
+                    if self.system.params.illegal == IllegalOpcodePolicy::Break {
+                        // open the debugger on the illegal opcode
+                        let mut panel = DebugPanel::new();
+                        panel.print(e.to_string());
+                        self.debug_panel = Some(panel);
+                    } else {
+                        eprintln!("{e}");
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 }
             }
-            ui.heading("My egui Application");
+        }
+        self.frames += 1;
+        self.elapsed += 1;
+        if self.system.params.frames.is_some_and(|f| self.elapsed >= f) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        if self.frames >= SAVE_INTERVAL {
+            self.flush();
+        }
+        let screen = self.update_screen(ctx);
+
+        CentralPanel::default().show(ctx, |ui| {
+            // only scale by whole numbers so every Game Boy pixel has the same size
+            let available = ui.available_size();
+            let scale = (available.x / SCREEN_WIDTH as f32)
+                .min(available.y / SCREEN_HEIGHT as f32)
+                .floor()
+                .max(1.0);
+            let size = egui::vec2(SCREEN_WIDTH as f32 * scale, SCREEN_HEIGHT as f32 * scale);
+            ui.centered_and_justified(|ui| ui.image((screen, size)));
+        });
+
+        // keep emulating even when there is no user input
+        ctx.request_repaint();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.flush();
+        if let Some(path) = &self.system.params.screenshot
+            && let Err(e) = screenshot::save(self.system.display.framebuffer(), path)
+        {
+            eprintln!("failed to write {}: {e}", path.display());
+        }
+    }
+}
+
+/// Debugger controls, the registers and a command line taking the same
+/// commands as the REPL.
+struct DebugPanel {
+    debugger: Debugger,
+    command: String,
+    log: Vec<String>,
+}
+
+impl DebugPanel {
+    fn new() -> DebugPanel {
+        DebugPanel {
+            debugger: Debugger::new(),
+            command: String::new(),
+            log: Vec::new(),
+        }
+    }
+
+    fn print(&mut self, text: String) {
+        if !text.is_empty() {
+            self.log.push(text);
+        }
+        let excess = self.log.len().saturating_sub(LOG_LINES);
+        self.log.drain(..excess);
+    }
+
+    fn execute(&mut self, command: Command, system: &mut System) {
+        let text = repl::execute(command, &mut self.debugger, system);
+        self.print(text);
+    }
+
+    /// Run up to a frame unless paused.
+    fn run_frame(&mut self, system: &mut System) {
+        if let Some(stop) = self.debugger.run_frame(system) {
+            self.print(stop.to_string());
+        }
+    }
+
+    /// Returns whether `quit` was entered.
+    fn show(&mut self, ctx: &Context, system: &mut System) -> bool {
+        let mut quit = false;
+        SidePanel::right("debugger").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                let name_label = ui.label("Your name: ");
-                ui.text_edit_singleline(&mut self.name)
-                    .labelled_by(name_label.id);
+                if self.debugger.paused() {
+                    if ui.button("Continue").clicked() {
+                        self.execute(Command::Continue, system);
+                    }
+                } else if ui.button("Pause").clicked() {
+                    self.debugger.pause();
+                }
+                for (label, command) in [
+                    ("Step", Command::Step),
+                    ("Next", Command::Next),
+                    ("Out", Command::Out),
+                    ("Frame", Command::Frames(1)),
+                ] {
+                    if ui.button(label).clicked() {
+                        self.execute(command, system);
+                    }
+                }
             });
-            ui.add(egui::Slider::new(&mut self.age, 0..=120).text("age"));
-            if ui.button("Increment").clicked() {
-                self.age += 1;
+            ui.monospace(system.cpu.registers.to_string());
+            let pc = system.cpu.registers.pc;
+            ui.monospace(repl::listing(system, pc, DISASSEMBLY_LINES).join("\n"));
+            ui.separator();
+
+            let input = ui.add(
+                TextEdit::singleline(&mut self.command)
+                    .code_editor()
+                    .hint_text("help"),
+            );
+            if input.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                let line = std::mem::take(&mut self.command);
+                match line.parse() {
+                    Ok(Command::Quit) => quit = true,
+                    Ok(command) => self.execute(command, system),
+                    Err(e) => self.print(e.to_string()),
+                }
+                input.request_focus();
             }
-            ui.label(format!("Hello '{}', age {}", self.name, self.age));
+            ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+                for line in &self.log {
+                    ui.monospace(line);
+                }
+            });
         });
+        quit
     }
 }
@@ -1,10 +1,13 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
 /// The joypad is represented in the memory address 0xFF00 and contains an interesting byte pattern:
 ///   | 7 | 6 | 5        | 4         | 3          | 2         | 1      | 0       |
 ///   | 1 | 1 | standard | direction | down/start | up/select | left/B | right/A |
 ///
 /// For example `start` is represented as:
 ///   | 1 | 1 | 0 | 1 | 0 | 1 | 1 | 1 |
-pub enum Joypad {
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Button {
     Down = 0b11100111,
     Up = 0b11101011,
     Left = 0b11101101,
@@ -14,3 +17,145 @@ pub enum Joypad {
     ButtonB = 0b11011101,
     ButtonA = 0b11011110,
 }
+
+impl Button {
+    /// Converts `"a"` to `Button::ButtonA`, `"start"` to `Button::Start`
+    /// and so on, ignoring case.
+    pub fn from_name(name: &str) -> Option<Button> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "down" => Button::Down,
+            "up" => Button::Up,
+            "left" => Button::Left,
+            "right" => Button::Right,
+            "start" => Button::Start,
+            "select" => Button::Select,
+            "b" => Button::ButtonB,
+            "a" => Button::ButtonA,
+            _ => return None,
+        })
+    }
+
+    /// Select bit (4 or 5) of the row the button belongs to.
+    fn row(self) -> u8 {
+        !(self as u8) & 0b0011_0000
+    }
+
+    /// Line (bits 0-3) the button pulls low when pressed.
+    fn line(self) -> u8 {
+        !(self as u8) & 0b0000_1111
+    }
+}
+
+/// State of the buttons as seen through JOYP. Both the select bits and the
+/// button lines are active low, a row is selected by writing 0 to its bit and
+/// a pressed button of a selected row reads as 0.
+#[derive(Debug)]
+pub struct Joypad {
+    // select bits as last written by the CPU
+    select: u8,
+    // pressed buttons per row, set bits are pressed
+    direction: u8,
+    standard: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0b0011_0000,
+            direction: 0,
+            standard: 0,
+        }
+    }
+
+    /// Levels of the four button lines for the selected rows.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0b0001_0000 == 0 {
+            pressed |= self.direction;
+        }
+        if self.select & 0b0010_0000 == 0 {
+            pressed |= self.standard;
+        }
+        !pressed & 0b0000_1111
+    }
+
+    /// Update a button and report whether the joypad interrupt should be
+    /// requested, which happens when any of the lines goes from high to low.
+    pub fn set(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.lines();
+        let row = match button.row() {
+            0b0001_0000 => &mut self.direction,
+            _ => &mut self.standard,
+        };
+        if pressed {
+            *row |= button.line();
+        } else {
+            *row &= !button.line();
+        }
+        before & !self.lines() != 0
+    }
+
+    pub fn read(&self) -> u8 {
+        0b1100_0000 | self.select | self.lines()
+    }
+
+    /// Only the select bits are writable, selecting a row with buttons held
+    /// down also pulls the lines low.
+    pub fn write(&mut self, val: u8) -> bool {
+        let before = self.lines();
+        self.select = val & 0b0011_0000;
+        before & !self.lines() != 0
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&[self.select, self.direction, self.standard]);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        let mut v = [0; 3];
+        r.bytes(&mut v)?;
+        [self.select, self.direction, self.standard] = v;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_test() {
+        let mut j = Joypad::new();
+        j.set(Button::Start, true);
+        j.set(Button::Left, true);
+        assert_eq!(j.read(), 0xFF);
+        j.write(0b0001_0000);
+        assert_eq!(j.read(), 0b1101_0111);
+        j.write(0b0010_0000);
+        assert_eq!(j.read(), 0b1110_1101);
+        j.write(0);
+        assert_eq!(j.read(), 0b1100_0101);
+    }
+
+    #[test]
+    fn interrupt_test() {
+        let mut j = Joypad::new();
+        // nothing is selected so no line can change
+        assert!(!j.set(Button::ButtonA, true));
+        assert!(j.write(0b0001_0000));
+        assert!(!j.set(Button::ButtonA, false));
+        assert!(j.set(Button::ButtonB, true));
+        // the line is already low
+        assert!(!j.set(Button::ButtonB, true));
+        // the direction row isn't selected
+        assert!(!j.set(Button::Down, true));
+    }
+}
@@ -1,38 +1,126 @@
+use cpu::IllegalOpcodePolicy;
+use eframe::egui::Key;
+use joypad::Button;
 use std::path::{Path, PathBuf};
 
 pub mod cartridge;
 pub mod cpu;
+pub mod debugger;
+pub mod dma;
+pub mod harness;
 pub mod interface;
+pub mod joypad;
 pub mod ppu;
 pub mod ram;
+pub mod serial;
 pub mod sound;
+pub mod state;
 pub mod system;
+pub mod timer;
+pub mod trace;
 
 // timing of hardware components
 pub const CPU_HZ: u32 = 4_194_304;
 pub const RAM_HZ: u32 = 1_048_576;
 pub const PPU_HZ: u32 = 4_194_304;
 pub const VRAM_HZ: u32 = 2_097_152;
+// T-cycles it takes the PPU to draw a full frame (154 lines of 456 dots)
+pub const FRAME_CYCLES: u32 = 70_224;
 
+/// Which Game Boy to emulate, the models differ in what their boot ROMs
+/// leave behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hardware {
+    // the first revision of the DMG boot ROM
+    Dmg0,
+    Dmg,
+    // Game Boy Pocket
+    Mgb,
+    Cgb,
+}
+
+impl Hardware {
+    /// Size of the model's boot ROM, the CGB one continues past the cartridge
+    /// header at 0x100-0x1FF up to 0x8FF.
+    pub fn boot_rom_size(&self) -> usize {
+        match self {
+            Hardware::Cgb => 0x900,
+            _ => 0x100,
+        }
+    }
+}
+
+/// Everything that decides how the system is brought up and run, filled in
+/// from the command line by `interface::cli`.
+#[derive(Debug, Clone)]
 pub struct BootParameters {
     pub rom_path: PathBuf,
     // false 8x8, true 8x16
     pub sprite_size: bool,
+    // run without a window
+    pub headless: bool,
+    // stop after this many frames, otherwise run until the window is closed
+    pub frames: Option<u32>,
+    // initial window scale of the 160x144 screen
+    pub scale: f32,
+    // boot ROM to run before the cartridge, without one the system starts in
+    // the state the model's boot ROM leaves it in
+    pub boot_rom: Option<PathBuf>,
+    // picked from the cartridge header when missing, a CGB for cartridges
+    // that support it
+    pub model: Option<Hardware>,
+    // where `.sav` files and save states go instead of next to the ROM
+    pub save_dir: Option<PathBuf>,
+    // file every executed instruction is logged to
+    pub trace: Option<PathBuf>,
+    // PNG of the last frame written when the emulator stops
+    pub screenshot: Option<PathBuf>,
+    // changes to the default key bindings in order, keys without a button
+    // are unbound
+    pub bindings: Vec<(Key, Option<Button>)>,
+    // start paused in the debugger
+    pub debug: bool,
+    // what the CPU does on an opcode it doesn't have
+    pub illegal: IllegalOpcodePolicy,
 }
 
 impl BootParameters {
-    pub fn new(p: Option<&str>) -> BootParameters {
-        let p = match p {
-            Some(v) => Path::new(v).to_path_buf(),
-            None => Path::new(env!("CARGO_MANIFEST_DIR"))
-                //.join("rom_tests/blarggs-test-roms/cpu_instrs/individual/01-special.gb"),
-                //.join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb"),
-                .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/04-op r,imm.gb"),
-            //.join("rom_tests/blarggs-test-roms/cpu_instrs/individual/10-bit ops.gb"),
+    pub fn new(rom_path: &Path) -> BootParameters {
+        BootParameters {
+            rom_path: rom_path.to_path_buf(),
+            ..BootParameters::default()
+        }
+    }
+
+    /// File next to the ROM, or in the save directory, named after the ROM
+    /// with the given extension, `game.gb` saves to `game.sav`.
+    pub fn save_path(&self, extension: &str) -> PathBuf {
+        let path = match (&self.save_dir, self.rom_path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => self.rom_path.clone(),
         };
+        path.with_extension(extension)
+    }
+}
+
+/// Everything but the ROM, the command line fills that in once it has gone
+/// through all the arguments.
+impl Default for BootParameters {
+    fn default() -> Self {
         BootParameters {
-            rom_path: p,
+            rom_path: PathBuf::new(),
             sprite_size: false,
+            headless: false,
+            frames: None,
+            scale: 3.0,
+            boot_rom: None,
+            model: None,
+            save_dir: None,
+            trace: None,
+            screenshot: None,
+            bindings: Vec::new(),
+            debug: false,
+            illegal: IllegalOpcodePolicy::Lock,
         }
     }
 }
@@ -1,11 +1,136 @@
+use std::path::Path;
 use they::BootParameters;
-use they::interface::window;
+use they::cpu::{IllegalOpcodePolicy, disassembler::disassemble};
+use they::interface::{
+    cli::{self, Command},
+    repl, screenshot, window,
+};
 use they::system::System;
+use they::trace;
 
 fn main() {
-    let boot_params = BootParameters::new(None);
-    let mut system = System::new(boot_params);
+    let boot_params = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(params)) => params,
+        Ok(Command::Disassemble { rom, from, count }) => {
+            list(&rom, from, count);
+            return;
+        }
+        Ok(Command::TraceDiff {
+            params,
+            log,
+            context,
+        }) => {
+            trace_diff(params, &log, context);
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let mut system = load(boot_params);
+    if let Some(trace) = system.params.trace.clone()
+        && let Err(e) = system.trace_to(&trace)
+    {
+        eprintln!("failed to create {}: {e}", trace.display());
+        std::process::exit(1);
+    }
+
+    if !system.params.headless {
+        window::run(system).ok();
+        return;
+    }
+    if system.params.debug {
+        debug(&mut system);
+    } else {
+        // without a window there's nothing to pace the frames, run them as
+        // fast as possible
+        let mut frames = 0;
+        while system.params.frames.is_none_or(|f| frames < f) {
+            if let Err(e) = system.run_frame() {
+                eprintln!("{e}");
+                if system.params.illegal != IllegalOpcodePolicy::Break {
+                    system.flush().ok();
+                    std::process::exit(1);
+                }
+                debug(&mut system);
+                break;
+            }
+            frames += 1;
+        }
+    }
+    if let Err(e) = system.flush() {
+        eprintln!("failed to write the save file: {e}");
+    }
+    if let Some(path) = &system.params.screenshot
+        && let Err(e) = screenshot::save(system.display.framebuffer(), path)
+    {
+        eprintln!("failed to write {}: {e}", path.display());
+        std::process::exit(1);
+    }
+}
+
+/// Run the debugger's REPL on the terminal.
+fn debug(system: &mut System) {
+    let stdin = std::io::stdin();
+    if let Err(e) = repl::run(system, stdin.lock(), std::io::stdout()) {
+        eprintln!("debugger failed: {e}");
+    }
+}
+
+/// Print `count` instructions of the ROM starting at `from`, addresses past
+/// the first 32 KiB aren't mapped to the cartridge.
+fn list(path: &Path, from: u16, count: u32) {
+    let rom = match std::fs::read(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("failed to read {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    };
+    let read = |addr: u16| match addr {
+        0x0000..=0x7FFF => rom.get(addr as usize).copied().unwrap_or(0xFF),
+        _ => 0xFF,
+    };
+    let mut addr = from;
+    for _ in 0..count {
+        let instruction = disassemble(addr, read);
+        println!("{}", instruction.line(addr));
+        addr = addr.wrapping_add(instruction.size());
+    }
+}
+
+/// Power on with the ROM, exiting when it can't be loaded.
+fn load(params: BootParameters) -> System {
+    let path = params.rom_path.clone();
+    let mut system = match System::new(params) {
+        Ok(system) => system,
+        Err(e) => {
+            eprintln!("failed to load {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    };
     system.initialize();
-    system.run();
-    window::run(system).ok();
+    system
+}
+
+/// Run the ROM against the reference log, exiting with 1 when they differ.
+fn trace_diff(params: BootParameters, path: &Path, context: usize) {
+    let mut system = load(params);
+    let result = trace::open_log(path).and_then(|log| trace::diff(&mut system, log, context));
+    match result {
+        Ok(None) => println!("matched the whole log"),
+        Ok(Some(divergence)) => {
+            println!("{divergence}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("failed to read {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
 }
@@ -1,6 +1,20 @@
-use crate::cpu::CPU;
+use crate::cpu::{CPU, Interrupt};
+use crate::ram::{Memory, MemoryRegister};
+use crate::state::{Reader, Snapshot, StateError, Writer};
 use std::ops::Range;
 
+const LINE_DOTS: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const VISIBLE_LINES: u8 = 144;
+const LINES: u8 = 154;
+const SPRITES_PER_LINE: usize = 10;
+// size of a VRAM bank, CGB mode has two
+pub const VRAM_BANK: usize = 0x2000;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Tile {
     pub pixels: [[Pixel; 8]; 8],
@@ -34,6 +48,14 @@ pub struct Display {
     pub wy: u8,
     pub wx: u8,
     pub stat: u8,
+    pub mode: Mode,
+    // dots spent on the current line
+    pub dots: u32,
+    // lines of the window drawn so far this frame
+    pub window_line: u8,
+    // STAT interrupts only trigger when any of the sources goes from low to high
+    stat_line: bool,
+    framebuffer: Box<[Pixel; SCREEN_WIDTH * SCREEN_HEIGHT]>,
 }
 
 impl Display {
@@ -55,6 +77,271 @@ impl Display {
             wy: 0,
             wx: 0,
             stat: 0,
+            mode: Mode::OamScan,
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+            framebuffer: Box::new([Pixel::White; SCREEN_WIDTH * SCREEN_HEIGHT]),
+        }
+    }
+
+    /// The last rendered frame, row by row from the top left corner.
+    pub fn framebuffer(&self) -> &[Pixel; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.framebuffer
+    }
+
+    /// Advance the PPU by the given amount of dots, walking through the modes
+    /// of every line and updating `LY` and the mode bits of `STAT`.
+    pub fn tick(&mut self, memory: &mut Memory, dots: u32) {
+        if memory.get_register(MemoryRegister::LCDC) & LCDC::PPUEnabled as u8 == 0 {
+            // a disabled LCD holds LY at 0 and restarts from the top once enabled
+            self.ly = 0;
+            self.dots = 0;
+            self.window_line = 0;
+            self.mode = Mode::OamScan;
+            self.stat_line = false;
+            memory.set_register(MemoryRegister::LY, 0);
+            let stat = memory.get_register(MemoryRegister::STAT) & 0b0111_1000;
+            memory.set_register(MemoryRegister::STAT, stat);
+            return;
+        }
+
+        let mut remaining = dots;
+        while remaining > 0 {
+            let step = remaining.min(self.mode_end() - self.dots);
+            self.dots += step;
+            remaining -= step;
+            if self.dots == self.mode_end() {
+                self.next_mode(memory);
+                self.update_registers(memory);
+            }
+        }
+        // LYC or the STAT sources could have been changed by the CPU
+        self.update_registers(memory);
+    }
+
+    /// The dot within a line at which the current mode ends.
+    fn mode_end(&self) -> u32 {
+        match self.mode {
+            Mode::OamScan => OAM_SCAN_DOTS,
+            Mode::Drawing => OAM_SCAN_DOTS + DRAWING_DOTS,
+            Mode::HBlank | Mode::VBlank => LINE_DOTS,
+        }
+    }
+
+    fn next_mode(&mut self, memory: &mut Memory) {
+        self.mode = match self.mode {
+            Mode::OamScan => Mode::Drawing,
+            Mode::Drawing => {
+                self.render_line(memory);
+                memory.hblank();
+                Mode::HBlank
+            }
+            Mode::HBlank | Mode::VBlank => {
+                self.dots = 0;
+                self.ly = (self.ly + 1) % LINES;
+                if self.ly == VISIBLE_LINES {
+                    memory.request_interrupt(Interrupt::VBlank);
+                    Mode::VBlank
+                } else if self.ly > VISIBLE_LINES {
+                    Mode::VBlank
+                } else {
+                    if self.ly == 0 {
+                        self.window_line = 0;
+                    }
+                    Mode::OamScan
+                }
+            }
+        };
+    }
+
+    /// Reflect LY and the mode in their registers and request the STAT
+    /// interrupt when one of the enabled sources becomes active.
+    ///
+    /// STAT:
+    ///   | 7 | 6        | 5      | 4      | 3      | 2        | 1-0  |
+    ///   | 1 | LYC int  | OAM int | VBlank int | HBlank int | LYC == LY | mode |
+    fn update_registers(&mut self, memory: &mut Memory) {
+        memory.set_register(MemoryRegister::LY, self.ly);
+        let coincidence = self.ly == memory.get_register(MemoryRegister::LYC);
+        let sources = memory.get_register(MemoryRegister::STAT) & 0b0111_1000;
+        self.stat = sources | (coincidence as u8) << 2 | self.mode as u8;
+        memory.set_register(MemoryRegister::STAT, self.stat);
+
+        let stat_line = (coincidence && sources & 0b0100_0000 != 0)
+            || match self.mode {
+                Mode::HBlank => sources & 0b0000_1000 != 0,
+                Mode::VBlank => sources & 0b0001_0000 != 0,
+                Mode::OamScan => sources & 0b0010_0000 != 0,
+                Mode::Drawing => false,
+            };
+        if stat_line && !self.stat_line {
+            memory.request_interrupt(Interrupt::LCDController);
+        }
+        self.stat_line = stat_line;
+    }
+
+    /// Compose the background, window and sprites of the current line into
+    /// the framebuffer.
+    fn render_line(&mut self, memory: &Memory) {
+        let lcdc = memory.get_register(MemoryRegister::LCDC);
+        let ly = self.ly;
+        // color indexes before the palette is applied, sprites need them to
+        // resolve their priority against the background
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+        // CGB tiles can be marked to stay in front of sprites
+        let mut bg_priority = [false; SCREEN_WIDTH];
+        let row = ly as usize * SCREEN_WIDTH;
+
+        // in CGB mode LCDC bit 0 doesn't turn the background off, it only
+        // takes away its priority over sprites
+        if memory.cgb || lcdc & LCDC::BgWindowPriority as u8 != 0 {
+            let bgp = memory.get_register(MemoryRegister::BGP);
+            let (scx, scy) = (
+                memory.get_register(MemoryRegister::SCX),
+                memory.get_register(MemoryRegister::SCY),
+            );
+            let (wx, wy) = (
+                memory.get_register(MemoryRegister::WX),
+                memory.get_register(MemoryRegister::WY),
+            );
+            let window = lcdc & LCDC::WindowEnabled as u8 != 0 && ly >= wy && wx <= 166;
+            let bg_map = lcdc & LCDC::BgTileMapArea as u8 != 0;
+            let window_map = lcdc & LCDC::WindowTileMapArea as u8 != 0;
+
+            let pixels = bg_colors.iter_mut().zip(bg_priority.iter_mut());
+            for (x, (bg_color, priority)) in pixels.enumerate() {
+                let (color, attributes) = if window && x + 7 >= wx as usize {
+                    let wx = (x + 7 - wx as usize) as u8;
+                    self.bg_color(memory, window_map, wx, self.window_line)
+                } else {
+                    self.bg_color(
+                        memory,
+                        bg_map,
+                        scx.wrapping_add(x as u8),
+                        scy.wrapping_add(ly),
+                    )
+                };
+                *bg_color = color;
+                *priority = attributes & 0b1000_0000 != 0;
+                self.framebuffer[row + x] = if memory.cgb {
+                    memory.bg_palettes.color(attributes & 0b111, color)
+                } else {
+                    Pixel::from_palette(bgp, color)
+                };
+            }
+            if window {
+                self.window_line += 1;
+            }
+        } else {
+            self.framebuffer[row..row + SCREEN_WIDTH].fill(Pixel::White);
+        }
+
+        if lcdc & LCDC::ObjEnabled as u8 != 0 {
+            self.render_sprites(memory, lcdc, &bg_colors, &bg_priority);
+        }
+    }
+
+    /// Color index of a pixel in one of the tile maps along with the
+    /// attributes of its tile. CGB mode keeps them in the second VRAM bank
+    /// at the same place as the tile number:
+    ///   * bits 0-2 - background palette
+    ///   * bit 3 - VRAM bank of the tile data
+    ///   * bit 5 - horizontal flip
+    ///   * bit 6 - vertical flip
+    ///   * bit 7 - drawn over sprites
+    fn bg_color(&self, memory: &Memory, map_area: bool, x: u8, y: u8) -> (u8, u8) {
+        let tile = self.get_tile(memory, map_area, x, y);
+        let attributes = match memory.cgb {
+            true => memory.vram[VRAM_BANK + map_offset(map_area, x, y)],
+            false => 0,
+        };
+        let (mut x, mut y) = (x % 8, y % 8);
+        if attributes & 0b0010_0000 != 0 {
+            x = 7 - x;
+        }
+        if attributes & 0b0100_0000 != 0 {
+            y = 7 - y;
+        }
+        let bank = attributes & 0b0000_1000 != 0;
+        (tile_color(memory, bank, tile, x, y), attributes)
+    }
+
+    fn render_sprites(
+        &mut self,
+        memory: &Memory,
+        lcdc: u8,
+        bg_colors: &[u8; SCREEN_WIDTH],
+        bg_priority: &[bool; SCREEN_WIDTH],
+    ) {
+        let height = if lcdc & LCDC::ObjSize as u8 != 0 {
+            16
+        } else {
+            8
+        };
+        let ly = self.ly as i16;
+        // OAM scan picks the first 10 sprites overlapping the line
+        let mut sprites: Vec<ObjectAttributeMap> = memory
+            .oam
+            .chunks_exact(4)
+            .map(|b| ObjectAttributeMap::new(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .filter(|s| {
+                let y = s.y as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+        // the sprite with the lowest x wins, then the earliest in OAM, so
+        // draw in reverse order to let those overwrite the rest. In CGB mode
+        // only the position in OAM counts unless OPRI asks for the DMG order
+        if !memory.cgb || memory.get_register(MemoryRegister::OPRI) & 1 != 0 {
+            sprites.sort_by_key(|s| s.x);
+        }
+        let row = self.ly as usize * SCREEN_WIDTH;
+        // with LCDC bit 0 cleared CGB sprites are always drawn on top
+        let cgb_priority = lcdc & LCDC::BgWindowPriority as u8 != 0;
+
+        for sprite in sprites.iter().rev() {
+            let palette = memory.get_register(if sprite.dmg_palette {
+                MemoryRegister::OBP1
+            } else {
+                MemoryRegister::OBP0
+            });
+            let mut line = (ly - (sprite.y as i16 - 16)) as u8;
+            if sprite.flip_y {
+                line = height as u8 - 1 - line;
+            }
+            let tile = if height == 16 {
+                (sprite.tile_idx & 0xFE) as usize + (line / 8) as usize
+            } else {
+                sprite.tile_idx as usize
+            };
+            let bank = memory.cgb && sprite.bank;
+
+            for px in 0..8u8 {
+                let x = sprite.x as i16 - 8 + px as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) {
+                    continue;
+                }
+                let x = x as usize;
+                let column = if sprite.flip_x { 7 - px } else { px };
+                let color = tile_color(memory, bank, tile, column, line % 8);
+                // color 0 is transparent, the background only covers a
+                // sprite where its own color isn't 0
+                let behind = bg_colors[x] != 0
+                    && match memory.cgb {
+                        true => cgb_priority && (sprite.priority || bg_priority[x]),
+                        false => sprite.priority,
+                    };
+                if color == 0 || behind {
+                    continue;
+                }
+                self.framebuffer[row + x] = if memory.cgb {
+                    memory.obj_palettes.color(sprite.cgb_palette, color)
+                } else {
+                    Pixel::from_palette(palette, color)
+                };
+            }
         }
     }
 
@@ -121,17 +408,40 @@ impl Display {
     /// values.
     pub fn load_tile_map(&mut self, cpu: &mut CPU) {
         for (i, addr) in (0x9800..0x9C00).enumerate() {
-            self.tile_map[0][i] = cpu.memory.ram[addr];
-            self.tile_map[1][i] = cpu.memory.ram[addr + 1024];
+            self.tile_map[0][i] = cpu.memory.read_byte(addr);
+            self.tile_map[1][i] = cpu.memory.read_byte(addr + 1024);
         }
     }
 
-    /// Return the index of the desired tile.
-    pub fn get_tile(&mut self) -> usize {
-        0
+    /// Return the index of the desired tile within `tiles` for the pixel at
+    /// (x, y) of one of the tile maps. Tile maps store tile numbers which are
+    /// either unsigned from 0x8000 or signed from 0x9000 depending on LCDC.
+    pub fn get_tile(&self, memory: &Memory, map_area: bool, x: u8, y: u8) -> usize {
+        let tile_num = memory.vram[map_offset(map_area, x, y)];
+        if memory.get_register(MemoryRegister::LCDC) & LCDC::WindowDataArea as u8 != 0 {
+            tile_num as usize
+        } else {
+            (256 + tile_num as i8 as isize) as usize
+        }
     }
 }
 
+/// Offset within VRAM of the tile map entry for the pixel at (x, y).
+fn map_offset(map_area: bool, x: u8, y: u8) -> usize {
+    let map = if map_area { 0x1C00 } else { 0x1800 };
+    map + (y as usize / 8) * 32 + (x as usize / 8)
+}
+
+/// Color index (0-3) of a pixel within a tile, every row of a tile is made up
+/// of two bytes, the first holds the low bit of each pixel and the second the
+/// high bit. CGB mode can take tiles from the second VRAM bank.
+fn tile_color(memory: &Memory, bank: bool, tile: usize, x: u8, y: u8) -> u8 {
+    let addr = bank as usize * VRAM_BANK + tile * 16 + y as usize * 2;
+    let (lb, hb) = (memory.vram[addr], memory.vram[addr + 1]);
+    let bit = 7 - x;
+    ((hb >> bit) & 1) << 1 | ((lb >> bit) & 1)
+}
+
 pub struct ObjectAttributeMap {
     pub x: u8,
     pub y: u8,
@@ -184,13 +494,146 @@ pub enum Sprite {
 ///   * Grey = 0x01
 ///   * DarkGrey = 0x10
 ///   * Black = 0x11
-#[repr(u32)]
+///
+/// In CGB mode pixels instead come from the color palettes, as 15-bit colors
+/// with 5 bits each for red, green and blue starting from the low bits.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Pixel {
-    Grey = 0x808080,
-    DarkGrey = 0x696969,
-    Black = 0x000000,
-    White = 0xFFFFFF,
+    Grey,
+    DarkGrey,
+    Black,
+    White,
+    Color(u16),
+}
+
+impl Pixel {
+    /// Palette registers (BGP, OBP0, OBP1) assign a shade to each of the four
+    /// color indexes using two bits per index, starting from the low bits.
+    pub fn from_palette(palette: u8, color: u8) -> Pixel {
+        match (palette >> (color * 2)) & 0b11 {
+            0 => Pixel::White,
+            1 => Pixel::Grey,
+            2 => Pixel::DarkGrey,
+            _ => Pixel::Black,
+        }
+    }
+
+    /// Split into its red, green and blue components.
+    pub fn rgb(self) -> [u8; 3] {
+        match self {
+            Pixel::Grey => [0x80; 3],
+            Pixel::DarkGrey => [0x69; 3],
+            Pixel::Black => [0x00; 3],
+            Pixel::White => [0xFF; 3],
+            Pixel::Color(c) => {
+                // scale 5 bits up to 8 so 0x1F becomes 0xFF
+                let channel = |shift: u16| {
+                    let v = (c >> shift & 0x1F) as u8;
+                    v << 3 | v >> 2
+                };
+                [channel(0), channel(5), channel(10)]
+            }
+        }
+    }
+
+    /// Compact form for save states, shades from lightest (0) to darkest (3)
+    /// have the top bit set to tell them apart from colors.
+    fn bits(&self) -> u16 {
+        match self {
+            Pixel::White => 0x8000,
+            Pixel::Grey => 0x8001,
+            Pixel::DarkGrey => 0x8002,
+            Pixel::Black => 0x8003,
+            Pixel::Color(c) => c & 0x7FFF,
+        }
+    }
+
+    fn from_bits(v: u16) -> Pixel {
+        match v & 0x8000 {
+            // the identity palette maps shades back to pixels
+            0x8000 => Pixel::from_palette(0b1110_0100, v as u8 & 0b11),
+            _ => Pixel::Color(v),
+        }
+    }
+}
+
+/// CGB color palette RAM, eight palettes of four 15-bit little endian colors.
+/// The CPU accesses it a byte at a time through a data register (BCPD, OCPD)
+/// at the index held by a specification register (BCPS, OCPS):
+///
+///   | 7              | 6      | 5-0   |
+///   | auto increment | unused | index |
+///
+/// With auto increment set every write to the data register moves the index
+/// to the next byte.
+#[derive(Debug)]
+pub struct PaletteRam {
+    pub data: [u8; 64],
+    spec: u8,
+}
+
+impl PaletteRam {
+    pub fn new() -> PaletteRam {
+        PaletteRam {
+            data: [0; 64],
+            spec: 0,
+        }
+    }
+
+    pub fn read_spec(&self) -> u8 {
+        self.spec | 0b0100_0000
+    }
+
+    pub fn write_spec(&mut self, val: u8) {
+        self.spec = val & 0b1011_1111;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[(self.spec & 0x3F) as usize]
+    }
+
+    pub fn write_data(&mut self, val: u8) {
+        self.data[(self.spec & 0x3F) as usize] = val;
+        if self.spec & 0x80 != 0 {
+            self.spec = 0x80 | (self.spec + 1) & 0x3F;
+        }
+    }
+
+    /// The color a palette assigns to a color index.
+    pub fn color(&self, palette: u8, color: u8) -> Pixel {
+        let i = (palette as usize * 4 + color as usize) * 2;
+        Pixel::Color(u16::from_le_bytes([self.data[i], self.data[i + 1]]) & 0x7FFF)
+    }
+}
+
+impl Default for PaletteRam {
+    fn default() -> Self {
+        PaletteRam::new()
+    }
+}
+
+impl Snapshot for PaletteRam {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.data);
+        w.u8(self.spec);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes(&mut self.data)?;
+        self.spec = r.u8()?;
+        Ok(())
+    }
+}
+
+/// The PPU cycles through OAM scan, drawing and HBlank on every visible line,
+/// lines 144 to 153 are spent entirely in VBlank. The value is what `STAT`
+/// reports in its lower two bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
 }
 
 /// Registers are actually 16 bit memory addresses to 8 bit storage, this enum
@@ -203,11 +646,11 @@ pub enum Register {
     SCX = 0xFF43,
     LY = 0xFF44,
     LYC = 0xFF45,
-    BGP = 0xFF46,
-    OBP0 = 0xFF47,
-    OBP1 = 0xFF48,
-    WY = 0xFF49,
-    WX = 0xFF4A,
+    BGP = 0xFF47,
+    OBP0 = 0xFF48,
+    OBP1 = 0xFF49,
+    WY = 0xFF4A,
+    WX = 0xFF4B,
 }
 
 /// LCD & PPU enable: 0 = Off; 1 = On
@@ -230,6 +673,41 @@ pub enum LCDC {
     BgWindowPriority = 0b0000_0001,
 }
 
+/// Only the timing state and the last frame are saved, everything the PPU
+/// draws from lives in memory.
+impl Snapshot for Display {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.ly);
+        w.u8(self.mode as u8);
+        w.u32(self.dots);
+        w.u8(self.window_line);
+        w.bool(self.stat_line);
+        w.u8(self.stat);
+        for p in self.framebuffer.iter() {
+            w.u16(p.bits());
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.ly = r.u8()?;
+        self.mode = match r.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(StateError::Invalid("PPU mode")),
+        };
+        self.dots = r.u32()?;
+        self.window_line = r.u8()?;
+        self.stat_line = r.bool()?;
+        self.stat = r.u8()?;
+        for p in self.framebuffer.iter_mut() {
+            *p = Pixel::from_bits(r.u16()?);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -268,6 +746,123 @@ mod tests {
         assert_eq!(cpu.memory.read_byte(Register::LCDC as u16), 0);
     }
 
+    #[test]
+    fn mode_timing_test() {
+        let (mut cpu, mut disp) = setup();
+        disp.tick(&mut cpu.memory, 80);
+        assert_eq!(disp.mode, Mode::Drawing);
+        disp.tick(&mut cpu.memory, 172);
+        assert_eq!(disp.mode, Mode::HBlank);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::STAT) & 0b11, 0);
+        disp.tick(&mut cpu.memory, 204);
+        assert_eq!(disp.mode, Mode::OamScan);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::LY), 1);
+
+        cpu.memory.register_write(MemoryRegister::IF, 0);
+        disp.tick(&mut cpu.memory, 456 * 143);
+        assert_eq!(disp.mode, Mode::VBlank);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::LY), 144);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::IF), 0xE1);
+        disp.tick(&mut cpu.memory, 456 * 10);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::LY), 0);
+        assert_eq!(disp.mode, Mode::OamScan);
+    }
+
+    fn render_setup() -> (CPU, Display) {
+        let (mut cpu, disp) = setup();
+        // LCD on, tile data at 0x8000, sprites and background on
+        cpu.memory.register_write(MemoryRegister::LCDC, 0x93);
+        cpu.memory.register_write(MemoryRegister::BGP, 0xE4);
+        cpu.memory.register_write(MemoryRegister::OBP0, 0xE4);
+        // tile 1 is solid color 3, tile 2 is solid color 1
+        cpu.memory.vram[16..32].fill(0xFF);
+        for row in 0..8 {
+            cpu.memory.vram[32 + row * 2] = 0xFF;
+        }
+        (cpu, disp)
+    }
+
+    #[test]
+    fn background_test() {
+        let (mut cpu, mut disp) = render_setup();
+        // second tile of the first map row
+        cpu.memory.vram[0x1801] = 1;
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        let fb = disp.framebuffer();
+        assert_eq!(fb[7], Pixel::White);
+        assert_eq!(fb[8], Pixel::Black);
+        assert_eq!(fb[15], Pixel::Black);
+        assert_eq!(fb[16], Pixel::White);
+
+        // scrolling moves the tile left
+        cpu.memory.register_write(MemoryRegister::SCX, 4);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        let fb = disp.framebuffer();
+        assert_eq!(fb[SCREEN_WIDTH + 3], Pixel::White);
+        assert_eq!(fb[SCREEN_WIDTH + 4], Pixel::Black);
+    }
+
+    #[test]
+    fn window_test() {
+        let (mut cpu, mut disp) = render_setup();
+        cpu.memory
+            .register_write(MemoryRegister::LCDC, 0x93 | LCDC::WindowEnabled as u8);
+        cpu.memory.register_write(MemoryRegister::WY, 0);
+        cpu.memory.register_write(MemoryRegister::WX, 7 + 80);
+        // the window uses the same map and covers the right half of the line
+        cpu.memory.vram[0x1800] = 1;
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        let fb = disp.framebuffer();
+        assert_eq!(fb[0], Pixel::Black);
+        assert_eq!(fb[8], Pixel::White);
+        assert_eq!(fb[80], Pixel::Black);
+        assert_eq!(fb[88], Pixel::White);
+        assert_eq!(disp.window_line, 1);
+    }
+
+    #[test]
+    fn sprite_test() {
+        let (mut cpu, mut disp) = render_setup();
+        // sprite at the top left corner using tile 2
+        cpu.memory.oam[0..4].copy_from_slice(&[16, 8, 2, 0]);
+        // a second sprite hidden behind a non zero background color
+        cpu.memory.oam[4..8].copy_from_slice(&[16, 24, 2, 0b1000_0000]);
+        cpu.memory.vram[0x1802] = 1;
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        let fb = disp.framebuffer();
+        assert_eq!(fb[0], Pixel::Grey);
+        assert_eq!(fb[8], Pixel::White);
+        assert_eq!(fb[16], Pixel::Black);
+
+        // sprites are drawn over background color 0 only with priority set
+        cpu.memory.oam[4..8].copy_from_slice(&[16, 40, 2, 0b1000_0000]);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(disp.framebuffer()[SCREEN_WIDTH + 32], Pixel::Grey);
+    }
+
+    #[test]
+    fn stat_interrupt_test() {
+        let (mut cpu, mut disp) = render_setup();
+        cpu.memory.register_write(MemoryRegister::IF, 0);
+        // HBlank source
+        cpu.memory.register_write(MemoryRegister::STAT, 0b0000_1000);
+        disp.tick(&mut cpu.memory, OAM_SCAN_DOTS + DRAWING_DOTS);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::IF), 0xE2);
+
+        // LYC source
+        cpu.memory.register_write(MemoryRegister::IF, 0);
+        cpu.memory.register_write(MemoryRegister::STAT, 0b0100_0000);
+        cpu.memory.register_write(MemoryRegister::LYC, 2);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::IF), 0xE0);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(cpu.memory.register_read(MemoryRegister::IF), 0xE2);
+        assert_eq!(
+            cpu.memory.register_read(MemoryRegister::STAT) & 0b100,
+            0b100
+        );
+    }
+
     #[test]
     fn oam_test() {
         let oam = ObjectAttributeMap::new(0xFFFFFFFF);
@@ -281,4 +876,73 @@ mod tests {
         assert!(oam.priority);
         assert!(oam.dmg_palette);
     }
+
+    #[test]
+    fn palette_ram_test() {
+        let mut p = PaletteRam::new();
+        p.write_spec(0x80 | 0x3E);
+        p.write_data(0x1F);
+        p.write_data(0x7C);
+        // the index wraps around
+        assert_eq!(p.read_spec(), 0xC0);
+        p.write_data(0xE0);
+        assert_eq!(p.color(7, 3), Pixel::Color(0x7C1F));
+        assert_eq!(p.color(0, 0), Pixel::Color(0x00E0));
+        assert_eq!(Pixel::Color(0x7C1F).rgb(), [0xFF, 0x00, 0xFF]);
+
+        // without auto increment the index stays put
+        p.write_spec(0x02);
+        p.write_data(0x12);
+        p.write_data(0x34);
+        assert_eq!(p.read_data(), 0x34);
+        assert_eq!(p.read_spec(), 0x42);
+    }
+
+    #[test]
+    fn cgb_test() {
+        let (mut cpu, mut disp) = render_setup();
+        cpu.memory.cgb = true;
+        // background palette 1 color 3 and sprite palette 2 color 1
+        cpu.memory.bg_palettes.data[14..16].copy_from_slice(&0x001Fu16.to_le_bytes());
+        cpu.memory.obj_palettes.data[18..20].copy_from_slice(&0x03E0u16.to_le_bytes());
+        // tile 1 in the second bank is solid color 3, the first map entry
+        // takes it from there with palette 1
+        cpu.memory.vram[VRAM_BANK + 16..VRAM_BANK + 32].fill(0xFF);
+        cpu.memory.vram[0x1800] = 1;
+        cpu.memory.vram[VRAM_BANK + 0x1800] = 0b0000_1001;
+        // a sprite over it using palette 2, and one behind a tile with the
+        // background priority bit
+        cpu.memory.oam[0..4].copy_from_slice(&[16, 12, 2, 0b0000_0010]);
+        cpu.memory.vram[0x1802] = 1;
+        cpu.memory.vram[VRAM_BANK + 0x1802] = 0b1000_0000;
+        cpu.memory.oam[4..8].copy_from_slice(&[16, 24, 2, 0b0000_0010]);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        let fb = disp.framebuffer();
+        assert_eq!(fb[0], Pixel::Color(0x001F));
+        assert_eq!(fb[4], Pixel::Color(0x03E0));
+        assert_eq!(fb[16], Pixel::Color(0x0000));
+
+        // with LCDC bit 0 cleared sprites go on top of everything
+        cpu.memory.register_write(MemoryRegister::LCDC, 0x92);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(disp.framebuffer()[SCREEN_WIDTH + 16], Pixel::Color(0x03E0));
+    }
+
+    #[test]
+    fn cgb_sprite_order_test() {
+        let (mut cpu, mut disp) = render_setup();
+        cpu.memory.cgb = true;
+        cpu.memory.obj_palettes.data[2..4].copy_from_slice(&0x0001u16.to_le_bytes());
+        cpu.memory.obj_palettes.data[10..12].copy_from_slice(&0x0002u16.to_le_bytes());
+        // the first sprite in OAM is further right but still wins
+        cpu.memory.oam[0..4].copy_from_slice(&[16, 12, 2, 0]);
+        cpu.memory.oam[4..8].copy_from_slice(&[16, 8, 2, 1]);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(disp.framebuffer()[4], Pixel::Color(0x0001));
+
+        // OPRI brings back the DMG order
+        cpu.memory.register_write(MemoryRegister::OPRI, 1);
+        disp.tick(&mut cpu.memory, LINE_DOTS);
+        assert_eq!(disp.framebuffer()[SCREEN_WIDTH + 4], Pixel::Color(0x0002));
+    }
 }
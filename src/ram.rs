@@ -1,4 +1,17 @@
-const RAM_SIZE: usize = 0x10000;
+use crate::Hardware;
+use crate::cartridge::Cartridge;
+use crate::cpu::Interrupt;
+use crate::debugger::{Access, WatchHit, Watchpoint};
+use crate::dma::{BLOCK_CYCLES, BLOCK_SIZE, Hdma, OamDma};
+use crate::joypad::{Button, Joypad};
+use crate::ppu::{PaletteRam, VRAM_BANK};
+use crate::serial::Serial;
+use crate::sound::Voices;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use crate::timer::Timer;
+
+// size of a work RAM bank, CGB mode switches between 7 for the upper one
+const WRAM_BANK: usize = 0x1000;
 
 #[repr(u16)]
 pub enum MemoryRegister {
@@ -63,19 +76,81 @@ pub enum MemoryRegister {
     IE = 0xFFFF,
 }
 
+/// The memory bus decodes every address to the hardware that owns it:
+///   * 0x0000-0x7FFF - cartridge ROM, writes program the bank controller
+///   * 0x8000-0x9FFF - video RAM, one of two banks in CGB mode
+///   * 0xA000-0xBFFF - cartridge RAM
+///   * 0xC000-0xCFFF - work RAM bank 0
+///   * 0xD000-0xDFFF - work RAM bank 1, or 1-7 in CGB mode
+///   * 0xE000-0xFDFF - echo of work RAM
+///   * 0xFE00-0xFE9F - object attribute memory
+///   * 0xFEA0-0xFEFF - unusable
+///   * 0xFF00-0xFF7F - I/O registers
+///   * 0xFF80-0xFFFE - high RAM
+///   * 0xFFFF - interrupt enable
+///
+/// Anything that isn't backed by hardware reads as open bus (0xFF).
 #[derive(Debug)]
 pub struct Memory {
-    pub ram: [u8; RAM_SIZE],
-    serial_out: Vec<u8>,
+    // without a cartridge inserted its ROM area acts as plain RAM
+    pub cartridge: Option<Cartridge>,
+    no_cartridge: Box<[u8; 0x8000]>,
+    // running a CGB cartridge on a CGB, which enables the banks and palettes
+    pub cgb: bool,
+    // CGB mode can run the CPU and timer at twice the speed
+    pub double_speed: bool,
+    pub oam_dma: Option<OamDma>,
+    pub hdma: Hdma,
+    // T-cycles the CPU is stopped for by VRAM DMA
+    stall: u32,
+    pub vram: Box<[u8; 2 * VRAM_BANK]>,
+    pub wram: Box<[u8; 8 * WRAM_BANK]>,
+    pub oam: [u8; 0xA0],
+    pub io: [u8; 0x80],
+    pub hram: [u8; 0x7F],
+    pub ie: u8,
+    pub timer: Timer,
+    pub joypad: Joypad,
+    pub sound: Voices,
+    pub bg_palettes: PaletteRam,
+    pub obj_palettes: PaletteRam,
+    pub serial: Serial,
+    // overlays the cartridge until 0xFF50 is written, empty without one
+    pub boot_rom: Vec<u8>,
+    pub boot_mapped: bool,
+    // CPU accesses to these addresses are reported to the debugger
+    pub watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
 }
 
 impl Memory {
     pub fn new() -> Memory {
         let mut m = Memory {
-            serial_out: Vec::new(),
-            ram: [0; RAM_SIZE],
+            serial: Serial::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            cartridge: None,
+            no_cartridge: Box::new([0; 0x8000]),
+            cgb: false,
+            double_speed: false,
+            oam_dma: None,
+            hdma: Hdma::new(),
+            stall: 0,
+            vram: Box::new([0; 2 * VRAM_BANK]),
+            wram: Box::new([0; 8 * WRAM_BANK]),
+            oam: [0; 0xA0],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            ie: 0,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            sound: Voices::new(),
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            boot_rom: Vec::new(),
+            boot_mapped: false,
         };
-        m.initialize();
+        m.initialize(Hardware::Dmg0);
         m
     }
 
@@ -87,8 +162,30 @@ impl Memory {
         self.write_byte(r as u16, v);
     }
 
-    // These could change depending on which GB version
-    fn initialize(&mut self) {
+    /// Hardware side access to a register, bypassing the bits which are read
+    /// only to the CPU.
+    pub(crate) fn set_register(&mut self, r: MemoryRegister, v: u8) {
+        self.io[(r as u16 - 0xFF00) as usize] = v;
+    }
+
+    pub(crate) fn get_register(&self, r: MemoryRegister) -> u8 {
+        self.io[(r as u16 - 0xFF00) as usize]
+    }
+
+    /// State at power on for the boot ROM to set up: the LCD and APU are off
+    /// and the divider starts from zero.
+    pub(crate) fn power_on(&mut self) {
+        self.io = [0; 0x80];
+        self.ie = 0;
+        self.timer = Timer::new();
+        self.write_byte(MemoryRegister::NR52 as u16, 0x00);
+        self.boot_mapped = !self.boot_rom.is_empty();
+    }
+
+    /// The registers as the model's boot ROM leaves them.
+    pub(crate) fn initialize(&mut self, model: Hardware) {
+        // the APU ignores register writes until it's powered on
+        self.write_byte(MemoryRegister::NR52 as u16, 0xF1);
         self.write_byte(MemoryRegister::NR10 as u16, 0x80);
         self.write_byte(MemoryRegister::NR11 as u16, 0xBF);
         self.write_byte(MemoryRegister::NR12 as u16, 0xF3);
@@ -104,7 +201,6 @@ impl Memory {
         self.write_byte(MemoryRegister::NR44 as u16, 0xBF);
         self.write_byte(MemoryRegister::NR50 as u16, 0x77);
         self.write_byte(MemoryRegister::NR51 as u16, 0xF3);
-        self.write_byte(MemoryRegister::NR52 as u16, 0xF1);
         self.write_byte(MemoryRegister::LCDC as u16, 0x91);
         self.write_byte(MemoryRegister::BGP as u16, 0xFC);
         self.write_byte(MemoryRegister::OBP0 as u16, 0xFF);
@@ -113,36 +209,494 @@ impl Memory {
         self.write_byte(MemoryRegister::DIV as u16, 0x18);
         self.write_byte(MemoryRegister::TAC as u16, 0xF8);
         self.write_byte(MemoryRegister::IF as u16, 0xE1);
+        if self.cgb {
+            // the boot ROM starts CGB cartridges with white backgrounds
+            for c in self.bg_palettes.data.chunks_exact_mut(2) {
+                c.copy_from_slice(&0x7FFFu16.to_le_bytes());
+            }
+        }
+        // the divider kept counting while the boot ROM ran, the CGB one takes
+        // a different time depending on the cartridge
+        self.timer.counter = match model {
+            Hardware::Dmg0 => 0x1800,
+            Hardware::Dmg | Hardware::Mgb => 0xAB00,
+            Hardware::Cgb => 0x0000,
+        };
+        self.boot_mapped = false;
+    }
+
+    /// Advance the components owned by memory by the given amount of T-cycles.
+    /// The timer and serial clock run off the CPU clock, so they count twice as
+    /// fast at double speed while the APU keeps its pace.
+    pub(crate) fn tick(&mut self, cycles: u32) {
+        let cpu_cycles = cycles << self.double_speed as u32;
+        if self.timer.tick(cpu_cycles) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        self.sound.tick(cycles);
+        if self.serial.tick(cpu_cycles) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        if let Some(mut dma) = self.oam_dma {
+            for i in dma.tick(cpu_cycles) {
+                let source = match dma.source + i as u16 {
+                    // past work RAM the DMA reads from its echo
+                    a @ 0xE000.. => a - 0x2000,
+                    a => a,
+                };
+                self.oam[i as usize] = self.bus_read(source);
+            }
+            self.oam_dma = (!dma.done()).then_some(dma);
+        }
+    }
+
+    /// The PPU entered HBlank on a visible line, when an HBlank VRAM DMA is
+    /// running it copies its next block.
+    pub(crate) fn hblank(&mut self) {
+        if self.hdma.hblank {
+            self.copy_block();
+        }
+    }
+
+    /// Copy a block of a VRAM DMA, which stops the CPU while it's going on.
+    fn copy_block(&mut self) {
+        let (source, dest) = self.hdma.next_block();
+        let bank = self.vram_index(0x8000);
+        for i in 0..BLOCK_SIZE {
+            let val = self.bus_read(source.wrapping_add(i));
+            self.vram[bank + (dest + i) as usize] = val;
+        }
+        self.stall += BLOCK_CYCLES;
+    }
+
+    /// T-cycles the CPU has to sit out for DMA since the last call.
+    pub(crate) fn take_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.stall)
     }
 
-    pub(crate) fn read_byte(&mut self, addr: u16) -> u8 {
-        //TODO: match on ranges for memory protection
-        self.ram[addr as usize]
+    /// STOP switches between normal and double speed in CGB mode once bit 0
+    /// of KEY1 has been set to prepare for it, returns whether it did.
+    ///
+    /// KEY1:
+    ///   | 7             | 6-1    | 0       |
+    ///   | current speed | unused | prepare |
+    pub(crate) fn switch_speed(&mut self) -> bool {
+        let key1 = self.get_register(MemoryRegister::KEY1);
+        if !self.cgb || key1 & 1 == 0 {
+            return false;
+        }
+        self.set_register(MemoryRegister::KEY1, 0);
+        self.double_speed = !self.double_speed;
+        // STOP resets the divider
+        self.timer.write(MemoryRegister::DIV as u16, 0);
+        true
+    }
+
+    /// Press or release a button, requesting the joypad interrupt when it
+    /// pulls one of the selected lines low.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set(button, pressed) {
+            self.request_interrupt(Interrupt::HiToLo);
+        }
+    }
+
+    /// Flag an interrupt in `IF`, it gets serviced once enabled through `IE`.
+    pub(crate) fn request_interrupt(&mut self, i: Interrupt) {
+        let v = self.get_register(MemoryRegister::IF);
+        self.set_register(MemoryRegister::IF, v | i.mask());
+    }
+
+    pub fn read_byte(&mut self, addr: u16) -> u8 {
+        // OAM DMA keeps the CPU off every bus but the one to the I/O
+        // registers and high RAM
+        if addr < 0xFF00 && self.oam_dma.is_some_and(|d| d.blocking()) {
+            return 0xFF;
+        }
+        let val = self.bus_read(addr);
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, Access::Read, val);
+        }
+        val
+    }
+
+    /// Read memory without the restrictions OAM DMA puts on the CPU and
+    /// without triggering watchpoints, for the debugger.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.bus_read(addr)
     }
 
-    pub(crate) fn read_word(&mut self, addr: u16) -> u16 {
-        self.read_byte(addr) as u16 | (self.read_byte(addr + 1) as u16) << 8
+    /// Read memory the way the DMA controllers see it, without the
+    /// restrictions OAM DMA puts on the CPU.
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // the CGB boot ROM leaves a gap for the cartridge header
+            0x0000..=0x00FF | 0x0200..=0x08FF
+                if self.boot_mapped && (addr as usize) < self.boot_rom.len() =>
+            {
+                self.boot_rom[addr as usize]
+            }
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => match &self.cartridge {
+                Some(cartridge) => cartridge.read(addr),
+                None if addr < 0x8000 => self.no_cartridge[addr as usize],
+                None => 0xFF,
+            },
+            0x8000..=0x9FFF => self.vram[self.vram_index(addr)],
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)],
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00],
+            // the DMG reads zeros from the unusable area
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(addr),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80],
+            0xFFFF => self.ie,
+        }
+    }
+
+    fn read_io(&self, addr: u16) -> u8 {
+        let idx = addr as usize - 0xFF00;
+        match addr {
+            0xFF00 => self.joypad.read(),
+            0xFF01 | 0xFF02 => self.serial.read(addr, self.cgb),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            0xFF10..=0xFF3F => self.sound.read(addr),
+            0xFF55 if self.cgb => self.hdma.read_status(),
+            0xFF4D if self.cgb => {
+                (self.double_speed as u8) << 7 | 0x7E | self.get_register(MemoryRegister::KEY1)
+            }
+            0xFF4F | 0xFF6C if self.cgb => self.io[idx] | 0xFE,
+            0xFF68 if self.cgb => self.bg_palettes.read_spec(),
+            0xFF69 if self.cgb => self.bg_palettes.read_data(),
+            0xFF6A if self.cgb => self.obj_palettes.read_spec(),
+            0xFF6B if self.cgb => self.obj_palettes.read_data(),
+            0xFF70 if self.cgb => self.io[idx] | 0xF8,
+            _ => self.io[idx] | io_read_mask(addr),
+        }
     }
 
-    pub(crate) fn write_byte(&mut self, addr: u16, val: u8) {
-        //TODO: match on ranges for memory protection
+    pub fn read_word(&mut self, addr: u16) -> u16 {
+        self.read_byte(addr) as u16 | (self.read_byte(addr.wrapping_add(1)) as u16) << 8
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        if addr < 0xFF00 && self.oam_dma.is_some_and(|d| d.blocking()) {
+            return;
+        }
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, Access::Write, val);
+        }
         match addr {
-            0xFF01 => self.serial_out.push(val),
-            _ => (),
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => match &mut self.cartridge {
+                Some(cartridge) => cartridge.write(addr, val),
+                None if addr < 0x8000 => self.no_cartridge[addr as usize] = val,
+                None => (),
+            },
+            0x8000..=0x9FFF => self.vram[self.vram_index(addr)] = val,
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)] = val,
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00] = val,
+            0xFEA0..=0xFEFF => (),
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80] = val,
+            0xFFFF => self.ie = val,
         }
-        self.ram[addr as usize] = val;
     }
 
-    pub(crate) fn write_word(&mut self, addr: u16, val: u16) {
+    fn write_io(&mut self, addr: u16, val: u8) {
+        let idx = addr as usize - 0xFF00;
+        match addr {
+            0xFF00 => {
+                if self.joypad.write(val) {
+                    self.request_interrupt(Interrupt::HiToLo);
+                }
+            }
+            0xFF01 | 0xFF02 => self.serial.write(addr, val, self.cgb),
+            0xFF04..=0xFF07 => self.timer.write(addr, val),
+            0xFF10..=0xFF3F => self.sound.write(addr, val, self.cgb),
+            // the mode and coincidence bits are controlled by the PPU
+            0xFF41 => self.io[idx] = (self.io[idx] & 0x07) | (val & 0x78),
+            // LY is read only
+            0xFF44 => (),
+            // the boot ROM unmaps itself and can't be mapped back
+            0xFF50 if val != 0 => self.boot_mapped = false,
+            0xFF46 => {
+                self.io[idx] = val;
+                self.oam_dma = Some(OamDma::new(val));
+            }
+            0xFF4D => self.io[idx] = val & 1,
+            0xFF51..=0xFF55 if self.cgb => {
+                let blocks = self.hdma.write(addr, val);
+                for _ in 0..blocks {
+                    self.copy_block();
+                }
+            }
+            0xFF68 if self.cgb => self.bg_palettes.write_spec(val),
+            0xFF69 if self.cgb => self.bg_palettes.write_data(val),
+            0xFF6A if self.cgb => self.obj_palettes.write_spec(val),
+            0xFF6B if self.cgb => self.obj_palettes.write_data(val),
+            _ => self.io[idx] = val,
+        }
+    }
+
+    pub fn write_word(&mut self, addr: u16, val: u16) {
         //lower nibble
         let v1: u8 = val as u8;
         //upper nibble
         let v2: u8 = (val >> 8) as u8;
         self.write_byte(addr, v1);
-        self.write_byte(addr + 1, v2);
+        self.write_byte(addr.wrapping_add(1), v2);
+    }
+
+    /// Remember the first access since the last `take_watch_hit` that a
+    /// watchpoint is set on.
+    fn watch(&mut self, addr: u16, access: Access, value: u8) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.hit(addr, access)) {
+            self.watch_hit = Some(WatchHit {
+                addr,
+                access,
+                value,
+            });
+        }
+    }
+
+    /// The access that hit a watchpoint since the last call, if any.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Offset within `vram` of an address in 0x8000-0x9FFF, VBK selects the
+    /// bank in CGB mode.
+    fn vram_index(&self, addr: u16) -> usize {
+        let bank = match self.cgb {
+            true => self.get_register(MemoryRegister::VBK) as usize & 1,
+            false => 0,
+        };
+        bank * VRAM_BANK + addr as usize - 0x8000
+    }
+
+    /// Offset within `wram` of an address in 0xC000-0xFDFF, taking the echo
+    /// into account. SVBK selects the upper bank in CGB mode where 0 selects
+    /// bank 1 as well.
+    fn wram_index(&self, addr: u16) -> usize {
+        let offset = (addr as usize - 0xC000) % (2 * WRAM_BANK);
+        if offset < WRAM_BANK {
+            return offset;
+        }
+        let bank = match self.cgb {
+            true => (self.get_register(MemoryRegister::SVBK) as usize & 0b111).max(1),
+            false => 1,
+        };
+        bank * WRAM_BANK + offset - WRAM_BANK
+    }
+}
+
+/// Bits of the I/O registers which aren't wired up and always read back as
+/// set, unmapped registers read as open bus.
+fn io_read_mask(addr: u16) -> u8 {
+    match addr {
+        0xFF0F => 0xE0,
+        0xFF41 => 0x80,
+        0xFF40 | 0xFF42..=0xFF4B => 0x00,
+        // write only or unmapped
+        _ => 0xFF,
+    }
+}
+
+impl Snapshot for Memory {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.cgb);
+        w.bool(self.double_speed);
+        w.bool(self.oam_dma.is_some());
+        if let Some(dma) = &self.oam_dma {
+            dma.save(w);
+        }
+        self.hdma.save(w);
+        w.u32(self.stall);
+        w.bytes(&self.vram[..]);
+        w.bytes(&self.wram[..]);
+        w.bytes(&self.oam);
+        w.bytes(&self.io);
+        w.bytes(&self.hram);
+        w.u8(self.ie);
+        self.timer.save(w);
+        self.joypad.save(w);
+        self.sound.save(w);
+        self.bg_palettes.save(w);
+        self.obj_palettes.save(w);
+        self.serial.save(w);
+        w.bool(self.boot_mapped);
+        match &self.cartridge {
+            Some(cartridge) => cartridge.save(w),
+            None => w.bytes(&self.no_cartridge[..]),
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.cgb = r.bool()?;
+        self.double_speed = r.bool()?;
+        self.oam_dma = match r.bool()? {
+            true => {
+                let mut dma = OamDma::new(0);
+                dma.load(r)?;
+                Some(dma)
+            }
+            false => None,
+        };
+        self.hdma.load(r)?;
+        self.stall = r.u32()?;
+        r.bytes(&mut self.vram[..])?;
+        r.bytes(&mut self.wram[..])?;
+        r.bytes(&mut self.oam)?;
+        r.bytes(&mut self.io)?;
+        r.bytes(&mut self.hram)?;
+        self.ie = r.u8()?;
+        self.timer.load(r)?;
+        self.joypad.load(r)?;
+        self.sound.load(r)?;
+        self.bg_palettes.load(r)?;
+        self.obj_palettes.load(r)?;
+        self.serial.load(r)?;
+        self.boot_mapped = r.bool()? && !self.boot_rom.is_empty();
+        match &mut self.cartridge {
+            Some(cartridge) => cartridge.load(r),
+            None => r.bytes(&mut self.no_cartridge[..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_ram_test() {
+        let mut m = Memory::new();
+        m.write_byte(0xC123, 0x42);
+        assert_eq!(m.read_byte(0xE123), 0x42);
+        m.write_byte(0xFDFF, 0x24);
+        assert_eq!(m.read_byte(0xDDFF), 0x24);
+    }
+
+    #[test]
+    fn cgb_banking_test() {
+        let mut m = Memory::new();
+        // banks are ignored outside CGB mode
+        m.register_write(MemoryRegister::SVBK, 3);
+        m.register_write(MemoryRegister::VBK, 1);
+        m.write_byte(0xD000, 0x11);
+        m.write_byte(0x8000, 0x22);
+        assert_eq!(m.wram[WRAM_BANK], 0x11);
+        assert_eq!(m.vram[0], 0x22);
+        assert_eq!(m.register_read(MemoryRegister::VBK), 0xFF);
+
+        m.cgb = true;
+        m.write_byte(0xD000, 0x33);
+        m.write_byte(0x8000, 0x44);
+        assert_eq!(m.wram[3 * WRAM_BANK], 0x33);
+        assert_eq!(m.read_byte(0xF000), 0x33);
+        assert_eq!(m.vram[VRAM_BANK], 0x44);
+        assert_eq!(m.register_read(MemoryRegister::VBK), 0xFF);
+        assert_eq!(m.register_read(MemoryRegister::SVBK), 0xFB);
+        // bank 0 selects bank 1
+        m.register_write(MemoryRegister::SVBK, 0);
+        m.register_write(MemoryRegister::VBK, 0);
+        assert_eq!(m.read_byte(0xD000), 0x11);
+        assert_eq!(m.read_byte(0x8000), 0x22);
+        assert_eq!(m.register_read(MemoryRegister::VBK), 0xFE);
+
+        m.register_write(MemoryRegister::BCPS, 0x81);
+        m.register_write(MemoryRegister::BCPD, 0x55);
+        assert_eq!(m.bg_palettes.data[1], 0x55);
+        assert_eq!(m.register_read(MemoryRegister::BCPS), 0xC2);
+    }
+
+    #[test]
+    fn oam_dma_test() {
+        let mut m = Memory::new();
+        for i in 0..0xA0 {
+            m.write_byte(0xC100 + i, i as u8);
+        }
+        m.write_byte(0xFF80, 0x42);
+        m.register_write(MemoryRegister::DMA, 0xC1);
+        m.tick(8);
+        // only high RAM and the I/O registers are reachable meanwhile
+        assert_eq!(m.read_byte(0xC100), 0xFF);
+        assert_eq!(m.read_byte(0xFF80), 0x42);
+        assert_eq!(m.register_read(MemoryRegister::DMA), 0xC1);
+        m.write_byte(0xC100, 0xAA);
+        assert_eq!(m.oam[0], 0);
+        m.tick(160 * 4);
+        assert!(m.oam_dma.is_none());
+        assert_eq!(m.read_byte(0xC100), 0);
+        assert_eq!(m.read_byte(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn vram_dma_test() {
+        let mut m = Memory::new();
+        m.cgb = true;
+        for i in 0..0x30 {
+            m.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        m.register_write(MemoryRegister::HDMA1, 0xC0);
+        m.register_write(MemoryRegister::HDMA2, 0x00);
+        m.register_write(MemoryRegister::HDMA3, 0x01);
+        m.register_write(MemoryRegister::HDMA4, 0x00);
+        // general purpose transfers copy everything at once
+        m.register_write(MemoryRegister::HDMA5, 0x01);
+        assert_eq!(m.vram[0x100..0x120], m.wram[..0x20]);
+        assert_eq!(m.take_stall(), 2 * BLOCK_CYCLES);
+        assert_eq!(m.register_read(MemoryRegister::HDMA5), 0xFF);
+
+        // HBlank transfers continue where the last one stopped
+        m.register_write(MemoryRegister::HDMA5, 0x80);
+        assert_eq!(m.register_read(MemoryRegister::HDMA5), 0x00);
+        assert_eq!(m.vram[0x120], 0);
+        m.hblank();
+        assert_eq!(m.vram[0x120..0x130], m.wram[0x20..0x30]);
+        assert_eq!(m.register_read(MemoryRegister::HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn unmapped_test() {
+        let mut m = Memory::new();
+        m.write_byte(0xFEA0, 0x42);
+        assert_eq!(m.read_byte(0xFEA0), 0x00);
+        m.write_byte(0xFF03, 0x42);
+        assert_eq!(m.read_byte(0xFF03), 0xFF);
+        assert_eq!(m.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn register_bits_test() {
+        let mut m = Memory::new();
+        m.register_write(MemoryRegister::IF, 0x00);
+        assert_eq!(m.register_read(MemoryRegister::IF), 0xE0);
+        m.register_write(MemoryRegister::NR52, 0x00);
+        assert_eq!(m.register_read(MemoryRegister::NR52), 0x70);
+        m.set_register(MemoryRegister::LY, 0x12);
+        m.register_write(MemoryRegister::LY, 0x34);
+        assert_eq!(m.register_read(MemoryRegister::LY), 0x12);
+        m.set_register(MemoryRegister::STAT, 0x03);
+        m.register_write(MemoryRegister::STAT, 0xFF);
+        assert_eq!(m.register_read(MemoryRegister::STAT), 0xFB);
+    }
+
+    #[test]
+    fn joypad_test() {
+        let mut m = Memory::new();
+        m.register_write(MemoryRegister::IF, 0x00);
+        m.register_write(MemoryRegister::JOYP, 0x20);
+        m.set_button(Button::Up, true);
+        assert_eq!(m.register_read(MemoryRegister::JOYP), 0xEB);
+        assert_eq!(m.register_read(MemoryRegister::IF), 0xF0);
     }
 
-    pub(crate) fn print_serial(&mut self) {
-        println!("{}", String::from_utf8_lossy(&self.serial_out));
+    #[test]
+    fn cartridge_rom_test() {
+        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/cpu_instrs.gb");
+        let mut m = Memory::new();
+        m.cartridge = Some(Cartridge::new(&p, &p.with_extension("sav")).unwrap());
+        let v = m.read_byte(0x0100);
+        m.write_byte(0x0100, !v);
+        assert_eq!(m.read_byte(0x0100), v);
     }
 }
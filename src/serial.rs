@@ -0,0 +1,292 @@
+use crate::ram::MemoryRegister;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// CPU T-cycles to shift a byte out at 8192 Hz
+const TRANSFER_CYCLES: u32 = 4096;
+// CPU T-cycles to shift a byte out with the CGB's fast clock, 262144 Hz
+const FAST_TRANSFER_CYCLES: u32 = 128;
+
+/// The other end of the link cable. Transfers are a byte at a time, both
+/// sides shift out their byte in SB and end up with the other's.
+pub trait SerialLink: fmt::Debug {
+    /// Exchange a byte while driving the clock, returning what came back.
+    fn transfer(&mut self, out: u8) -> u8;
+
+    /// Wait for the other side's clock with a byte ready to go, returning
+    /// what it sent once it started a transfer.
+    fn receive(&mut self, out: u8) -> Option<u8>;
+}
+
+/// No cable, the input line floats high and nothing ever drives the clock.
+#[derive(Debug)]
+pub struct Unplugged;
+
+impl SerialLink for Unplugged {
+    fn transfer(&mut self, _out: u8) -> u8 {
+        0xFF
+    }
+
+    fn receive(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Output wired back into the input, every byte comes back as it was sent.
+#[derive(Debug)]
+pub struct Loopback;
+
+impl SerialLink for Loopback {
+    fn transfer(&mut self, out: u8) -> u8 {
+        out
+    }
+
+    fn receive(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Keeps every byte sent, which is how test ROMs report their results.
+/// Clones share the same buffer so it can be read while the system owns the
+/// link.
+#[derive(Debug, Clone)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture(Rc::new(RefCell::new(Vec::new())))
+    }
+
+    /// Every byte sent so far.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture::new()
+    }
+}
+
+impl SerialLink for Capture {
+    fn transfer(&mut self, out: u8) -> u8 {
+        self.0.borrow_mut().push(out);
+        0xFF
+    }
+
+    fn receive(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// The shared state of a cable between two systems.
+#[derive(Debug)]
+struct Cable {
+    // byte each end has ready while waiting for the other's clock
+    waiting: [Option<u8>; 2],
+    // byte sent to each end while it was waiting
+    delivered: [Option<u8>; 2],
+}
+
+/// One end of a cable between two systems running in the same process.
+#[derive(Debug)]
+pub struct Linked {
+    cable: Rc<RefCell<Cable>>,
+    end: usize,
+}
+
+/// A cable with both of its ends, plug one into each system.
+pub fn linked() -> (Linked, Linked) {
+    let cable = Rc::new(RefCell::new(Cable {
+        waiting: [None; 2],
+        delivered: [None; 2],
+    }));
+    (
+        Linked {
+            cable: cable.clone(),
+            end: 0,
+        },
+        Linked { cable, end: 1 },
+    )
+}
+
+impl SerialLink for Linked {
+    fn transfer(&mut self, out: u8) -> u8 {
+        let mut cable = self.cable.borrow_mut();
+        let other = 1 - self.end;
+        match cable.waiting[other].take() {
+            Some(incoming) => {
+                cable.delivered[other] = Some(out);
+                incoming
+            }
+            // the other side isn't listening
+            None => 0xFF,
+        }
+    }
+
+    fn receive(&mut self, out: u8) -> Option<u8> {
+        let mut cable = self.cable.borrow_mut();
+        let incoming = cable.delivered[self.end].take();
+        cable.waiting[self.end] = incoming.is_none().then_some(out);
+        incoming
+    }
+}
+
+/// The serial port shifts SB out to the link while shifting the other side's
+/// byte in, then requests the serial interrupt. SC controls the transfer:
+///
+///   | 7                 | 6-2    | 1                | 0     |
+///   | transfer enabled  | unused | fast clock (CGB) | clock |
+///
+/// With bit 0 set the port drives the clock itself, otherwise it waits for
+/// the other side's.
+#[derive(Debug)]
+pub struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+    // CPU T-cycles left of a transfer on the internal clock
+    cycles: u32,
+    pub link: Box<dyn SerialLink>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cycles: 0,
+            link: Box::new(Unplugged),
+        }
+    }
+
+    pub fn read(&self, addr: u16, cgb: bool) -> u8 {
+        match addr {
+            a if a == MemoryRegister::SB as u16 => self.sb,
+            // the fast clock bit only exists in CGB mode
+            _ => self.sc | if cgb { 0x7C } else { 0x7E },
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8, cgb: bool) {
+        match addr {
+            a if a == MemoryRegister::SB as u16 => self.sb = val,
+            _ => {
+                self.sc = val & if cgb { 0x83 } else { 0x81 };
+                self.cycles = match self.sc & 0b10 {
+                    0 => TRANSFER_CYCLES,
+                    _ => FAST_TRANSFER_CYCLES,
+                };
+            }
+        }
+    }
+
+    /// Advance by the given CPU T-cycles and report whether a transfer
+    /// completed, which requests the serial interrupt.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if self.sc & 0x80 == 0 {
+            return false;
+        }
+        let incoming = if self.sc & 1 == 0 {
+            match self.link.receive(self.sb) {
+                Some(incoming) => incoming,
+                None => return false,
+            }
+        } else {
+            self.cycles = self.cycles.saturating_sub(cycles);
+            if self.cycles > 0 {
+                return false;
+            }
+            self.link.transfer(self.sb)
+        };
+        self.sb = incoming;
+        self.sc &= 0x7F;
+        true
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Serial::new()
+    }
+}
+
+/// The link isn't part of the state, it stays plugged in across loads.
+impl Snapshot for Serial {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u32(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.cycles = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SB: u16 = MemoryRegister::SB as u16;
+    const SC: u16 = MemoryRegister::SC as u16;
+
+    #[test]
+    fn internal_clock_test() {
+        let mut serial = Serial::new();
+        serial.link = Box::new(Loopback);
+        serial.write(SB, 0x42, false);
+        serial.write(SC, 0x81, false);
+        assert_eq!(serial.read(SC, false), 0xFF);
+        assert!(!serial.tick(TRANSFER_CYCLES - 4));
+        assert!(serial.tick(4));
+        assert_eq!(serial.read(SB, false), 0x42);
+        assert_eq!(serial.read(SC, false), 0x7F);
+        // done until the next transfer
+        assert!(!serial.tick(TRANSFER_CYCLES));
+
+        // without a cable the input reads high
+        serial.link = Box::new(Unplugged);
+        serial.write(SC, 0x83, true);
+        assert_eq!(serial.read(SC, true), 0xFF);
+        assert!(serial.tick(FAST_TRANSFER_CYCLES));
+        assert_eq!(serial.sb, 0xFF);
+    }
+
+    #[test]
+    fn capture_test() {
+        let mut serial = Serial::new();
+        let capture = Capture::new();
+        serial.link = Box::new(capture.clone());
+        for b in b"ok" {
+            serial.write(SB, *b, false);
+            serial.write(SC, 0x81, false);
+            serial.tick(TRANSFER_CYCLES);
+        }
+        assert_eq!(capture.bytes(), b"ok");
+    }
+
+    #[test]
+    fn linked_test() {
+        let (a, b) = linked();
+        let (mut master, mut slave) = (Serial::new(), Serial::new());
+        master.link = Box::new(a);
+        slave.link = Box::new(b);
+        master.write(SB, 0x12, false);
+        slave.write(SB, 0x34, false);
+        // the slave waits for the master's clock
+        slave.write(SC, 0x80, false);
+        assert!(!slave.tick(TRANSFER_CYCLES));
+        master.write(SC, 0x81, false);
+        assert!(master.tick(TRANSFER_CYCLES));
+        assert_eq!(master.sb, 0x34);
+        assert!(slave.tick(4));
+        assert_eq!(slave.sb, 0x12);
+        assert_eq!(slave.sc, 0x00);
+    }
+}
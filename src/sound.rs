@@ -1,52 +1,399 @@
-/// Sound contains 4 distinct voices with
+use crate::CPU_HZ;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+use std::collections::VecDeque;
+
+pub mod channels;
+
+use channels::{Noise, Pulse, Wave};
+
+// the frame sequencer clocks length, envelope and sweep units at 512 Hz
+const FRAME_SEQUENCER_CYCLES: u32 = CPU_HZ / 512;
+// output rate used until the host asks for something else
+pub const SAMPLE_RATE: u32 = 48_000;
+// samples nobody drained are dropped past this many stereo pairs
+const MAX_BUFFERED: usize = SAMPLE_RATE as usize / 4;
+
+/// Bits of NR10-NR52 which always read back as set, indexed from 0xFF10.
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+/// Sound contains 4 distinct voices with their own registers:
+///   1. Pulse with a frequency sweep (NR10-NR14)
+///   2. Pulse (NR21-NR24)
+///   3. Wave playing back wave RAM (NR30-NR34, 0xFF30-0xFF3F)
+///   4. Noise (NR41-NR44)
+///
+/// They're mixed into a left and right output through NR50 and NR51 which
+/// gets resampled from the CPU clock down to the host's sample rate.
 #[derive(Debug)]
 pub struct Voices {
-    pub pulse_a: (Register, Register, Register, Register, Register),
-    pub pulse_b: (Register, Register, Register, Register, Register),
-    pub wave: (Register, Register, Register, Register, Register),
-    pub noise: (Register, Register, Register, Register, Register),
+    // step (0-7) the frame sequencer runs next
+    pub frame_step: u8,
+    // T-cycles accumulated towards the next frame sequencer step
+    sequencer_cycles: u32,
+    pub pulse_a: Pulse,
+    pub pulse_b: Pulse,
+    pub wave: Wave,
+    pub noise: Noise,
+    // NR52 bit 7, everything but wave RAM is cleared while it's off, the DMG
+    // also keeps the length counters
+    pub power: bool,
+    // raw NR10-NR51 as written, the masks in `READ_MASKS` hide the write
+    // only bits
+    registers: [u8; 0x16],
+    sample_rate: u32,
+    // CPU clock scaled by the sample rate, towards the next output sample
+    sample_phase: u64,
+    // output summed over the cycles of the current sample
+    left: f32,
+    right: f32,
+    summed_cycles: u32,
+    // interleaved left and right samples waiting to be played, the oldest
+    // are dropped from the front once it's full
+    samples: VecDeque<f32>,
 }
 
 impl Voices {
     pub fn new() -> Voices {
         Voices {
-            pulse_a: (
-                Register::Control(0),
-                Register::Frequency(0),
-                Register::Volume(0),
-                Register::Length(0),
-                Register::Sweep(0),
-            ),
-            pulse_b: (
-                Register::Control(0),
-                Register::Frequency(0),
-                Register::Volume(0),
-                Register::Length(0),
-                Register::Sweep(0),
-            ),
-            wave: (
-                Register::Control(0),
-                Register::Frequency(0),
-                Register::Volume(0),
-                Register::Length(0),
-                Register::Sweep(0),
-            ),
-            noise: (
-                Register::Control(0),
-                Register::Frequency(0),
-                Register::Volume(0),
-                Register::Length(0),
-                Register::Sweep(0),
-            ),
+            frame_step: 0,
+            sequencer_cycles: 0,
+            pulse_a: Pulse::new(true),
+            pulse_b: Pulse::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            power: false,
+            registers: [0; 0x16],
+            sample_rate: SAMPLE_RATE,
+            sample_phase: 0,
+            left: 0.0,
+            right: 0.0,
+            summed_cycles: 0,
+            samples: VecDeque::with_capacity(MAX_BUFFERED * 2),
+        }
+    }
+
+    /// Advance the sound hardware by the given amount of T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        if self.power {
+            self.pulse_a.tick(cycles);
+            self.pulse_b.tick(cycles);
+            self.wave.tick(cycles);
+            self.noise.tick(cycles);
+
+            self.sequencer_cycles += cycles;
+            while self.sequencer_cycles >= FRAME_SEQUENCER_CYCLES {
+                self.sequencer_cycles -= FRAME_SEQUENCER_CYCLES;
+                self.step_sequencer();
+            }
+        }
+        self.resample(cycles);
+    }
+
+    /// Length counters are clocked on even steps, sweep on 2 and 6 and the
+    /// envelopes on 7.
+    fn step_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.pulse_a.clock_length();
+            self.pulse_b.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.pulse_a.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.pulse_a.clock_envelope();
+            self.pulse_b.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    /// Current left and right output in the range -1.0 to 1.0.
+    pub fn output(&self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
         }
+        let channels = [
+            (self.pulse_a.dac(), self.pulse_a.output()),
+            (self.pulse_b.dac(), self.pulse_b.output()),
+            (self.wave.dac(), self.wave.output()),
+            (self.noise.dac(), self.noise.output()),
+        ];
+        let nr51 = self.registers[0x15];
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, (dac, amplitude)) in channels.into_iter().enumerate() {
+            // a DAC that's off outputs nothing, otherwise 0-15 maps to 1.0..-1.0
+            if !dac {
+                continue;
+            }
+            let v = 1.0 - amplitude as f32 / 7.5;
+            if nr51 & (0x10 << i) != 0 {
+                left += v;
+            }
+            if nr51 & (0x01 << i) != 0 {
+                right += v;
+            }
+        }
+        let nr50 = self.registers[0x14];
+        let volume = |v: u8| (v & 0b111) as f32 + 1.0;
+        (
+            left / 4.0 * volume(nr50 >> 4) / 8.0,
+            right / 4.0 * volume(nr50) / 8.0,
+        )
+    }
+
+    /// Average the output over each sample period of the host rate.
+    fn resample(&mut self, cycles: u32) {
+        let (left, right) = self.output();
+        self.left += left * cycles as f32;
+        self.right += right * cycles as f32;
+        self.summed_cycles += cycles;
+        self.sample_phase += cycles as u64 * self.sample_rate as u64;
+        if self.sample_phase < CPU_HZ as u64 {
+            return;
+        }
+        self.sample_phase %= CPU_HZ as u64;
+        let n = self.summed_cycles as f32;
+        if self.samples.len() >= MAX_BUFFERED * 2 {
+            self.samples.pop_front();
+            self.samples.pop_front();
+        }
+        self.samples.extend([self.left / n, self.right / n]);
+        self.left = 0.0;
+        self.right = 0.0;
+        self.summed_cycles = 0;
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Take the interleaved left and right samples produced so far.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => {
+                let status = [
+                    self.pulse_a.enabled,
+                    self.pulse_b.enabled,
+                    self.wave.enabled,
+                    self.noise.enabled,
+                ]
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, &on)| acc | (on as u8) << i);
+                0x70 | (self.power as u8) << 7 | status
+            }
+            0xFF10..=0xFF25 => {
+                let idx = addr as usize - 0xFF10;
+                self.registers[idx] | READ_MASKS[idx]
+            }
+            0xFF30..=0xFF3F => self.wave.ram[self.wave.ram_index(addr as usize - 0xFF30)],
+            _ => 0xFF,
+        }
+    }
+
+    /// The CGB clears the length counters along with everything else when
+    /// powered off, the DMG keeps them and lets them be written while off.
+    pub fn write(&mut self, addr: u16, val: u8, cgb: bool) {
+        // wave RAM doesn't depend on power
+        if let 0xFF30..=0xFF3F = addr {
+            let idx = self.wave.ram_index(addr as usize - 0xFF30);
+            self.wave.ram[idx] = val;
+            return;
+        }
+        if addr == 0xFF26 {
+            self.write_power(val & 0x80 != 0, cgb);
+            return;
+        }
+        if !self.power {
+            if cgb {
+                return;
+            }
+            match addr {
+                0xFF11 | 0xFF16 | 0xFF20 => self.write_channel(addr, val & 0b0011_1111),
+                0xFF1B => self.write_channel(addr, val),
+                _ => (),
+            }
+            return;
+        }
+        if let 0xFF10..=0xFF25 = addr {
+            self.registers[addr as usize - 0xFF10] = val;
+            self.write_channel(addr, val);
+        }
+    }
+
+    fn write_channel(&mut self, addr: u16, val: u8) {
+        let odd_step = self.frame_step % 2 == 1;
+        let idx = (addr as usize - 0xFF10) % 5;
+        match addr {
+            0xFF10..=0xFF14 => self.pulse_a.write(idx, val, odd_step),
+            0xFF15..=0xFF19 => self.pulse_b.write(idx, val, odd_step),
+            0xFF1A..=0xFF1E => self.wave.write(idx, val, odd_step),
+            0xFF1F..=0xFF23 => self.noise.write(idx, val, odd_step),
+            _ => (),
+        }
+    }
+
+    fn write_power(&mut self, on: bool, cgb: bool) {
+        if on && !self.power {
+            // the next step after powering on is 0
+            self.frame_step = 0;
+            self.sequencer_cycles = 0;
+        } else if !on && self.power {
+            self.registers = [0; 0x16];
+            self.pulse_a.power_off();
+            self.pulse_b.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+            if cgb {
+                for length in [
+                    &mut self.pulse_a.length,
+                    &mut self.pulse_b.length,
+                    &mut self.wave.length,
+                    &mut self.noise.length,
+                ] {
+                    length.counter = 0;
+                }
+            }
+        }
+        self.power = on;
     }
 }
 
-#[derive(Debug)]
-pub enum Register {
-    Control(u8),
-    Frequency(u8),
-    Volume(u8),
-    Length(u8),
-    Sweep(u8),
+/// The resampler isn't saved, it only holds output the host hasn't played yet.
+impl Snapshot for Voices {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.frame_step);
+        w.u32(self.sequencer_cycles);
+        w.bool(self.power);
+        w.bytes(&self.registers);
+        self.pulse_a.save(w);
+        self.pulse_b.save(w);
+        self.wave.save(w);
+        self.noise.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.frame_step = r.u8()?;
+        self.sequencer_cycles = r.u32()?;
+        self.power = r.bool()?;
+        r.bytes(&mut self.registers)?;
+        self.pulse_a.load(r)?;
+        self.pulse_b.load(r)?;
+        self.wave.load(r)?;
+        self.noise.load(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered() -> Voices {
+        let mut v = Voices::new();
+        v.write(0xFF26, 0x80, false);
+        v
+    }
+
+    #[test]
+    fn power_test() {
+        let mut v = powered();
+        v.write(0xFF12, 0xF0, false);
+        v.write(0xFF30, 0x12, false);
+        v.write(0xFF14, 0x80, false);
+        assert_eq!(v.read(0xFF26), 0xF1);
+        v.write(0xFF26, 0x00, false);
+        assert_eq!(v.read(0xFF26), 0x70);
+        assert_eq!(v.read(0xFF12), 0x00);
+        v.write(0xFF12, 0xF0, false);
+        assert_eq!(v.read(0xFF12), 0x00);
+        assert_eq!(v.read(0xFF30), 0x12);
+    }
+
+    #[test]
+    fn cgb_power_test() {
+        for cgb in [false, true] {
+            let mut v = powered();
+            v.write(0xFF20, 0x3F, cgb);
+            v.write(0xFF26, 0x00, cgb);
+            v.write(0xFF1B, 0xFF, cgb);
+            // only the DMG keeps the length counters and lets them be written
+            let counters = match cgb {
+                false => (1, 1),
+                true => (0, 0),
+            };
+            assert_eq!((v.noise.length.counter, v.wave.length.counter), counters);
+        }
+    }
+
+    #[test]
+    fn length_test() {
+        let mut v = powered();
+        v.write(0xFF12, 0xF0, false);
+        // 63 leaves a single step
+        v.write(0xFF11, 0x3F, false);
+        v.write(0xFF14, 0xC0, false);
+        assert_eq!(v.read(0xFF26) & 1, 1);
+        v.tick(FRAME_SEQUENCER_CYCLES);
+        assert_eq!(v.read(0xFF26) & 1, 0);
+    }
+
+    #[test]
+    fn sweep_overflow_test() {
+        let mut v = powered();
+        v.write(0xFF12, 0xF0, false);
+        v.write(0xFF10, 0x11, false);
+        v.write(0xFF13, 0xFF, false);
+        // 0x7FF + (0x7FF >> 1) overflows as soon as the channel triggers
+        v.write(0xFF14, 0x87, false);
+        assert_eq!(v.read(0xFF26) & 1, 0);
+    }
+
+    #[test]
+    fn noise_lfsr_test() {
+        let mut n = Noise::new();
+        n.write(2, 0xF0, false);
+        n.write(4, 0x80, false);
+        // divisor 8 with no shift, the first clock shifts in a 0
+        n.tick(8);
+        assert_eq!(n.output(), 0);
+        n.tick(8 * 14);
+        assert_eq!(n.output(), 15);
+    }
+
+    #[test]
+    fn resample_test() {
+        let mut v = powered();
+        v.write(0xFF24, 0x77, false);
+        v.write(0xFF25, 0xFF, false);
+        // 1/64th of a second, 4 T-cycles at a time like the CPU does
+        for _ in 0..CPU_HZ / 64 / 4 {
+            v.tick(4);
+        }
+        let samples = v.drain_samples();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize / 64 * 2);
+        assert!(samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(v.drain_samples().is_empty());
+
+        // half a second without draining only keeps the last quarter
+        for _ in 0..CPU_HZ / 2 / 64 {
+            v.tick(64);
+        }
+        assert_eq!(v.drain_samples().len(), MAX_BUFFERED * 2);
+    }
 }
@@ -0,0 +1,641 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+// duty cycles of the pulse channels, one bit per step
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+// base periods of the noise channel selected by NR43
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Turns a channel off once enough frame sequencer steps have passed. The
+/// counter counts up from the written value and the channel stops when it
+/// overflows, so it's stored as the steps left.
+#[derive(Debug)]
+pub struct Length {
+    pub enabled: bool,
+    pub counter: u16,
+    max: u16,
+}
+
+impl Length {
+    fn new(max: u16) -> Length {
+        Length {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    fn load(&mut self, val: u8) {
+        self.counter = self.max - val as u16;
+    }
+
+    /// Returns false when the channel has to be turned off.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+
+    /// Handle the length enable bit and trigger of a NRx4 write. Enabling the
+    /// length while the frame sequencer's next step doesn't clock it clocks it
+    /// once more. Returns false when the channel has to be turned off.
+    fn write(&mut self, val: u8, odd_step: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = val & 0b0100_0000 != 0;
+        let trigger = val & 0b1000_0000 != 0;
+        let mut on = true;
+        if !was_enabled && self.enabled && odd_step && self.counter != 0 {
+            self.counter -= 1;
+            on = self.counter != 0 || trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && odd_step {
+                self.counter -= 1;
+            }
+        }
+        on
+    }
+}
+
+/// Changes the volume of a channel over time.
+///
+/// NRx2:
+///   | 7-4            | 3                 | 2-0    |
+///   | initial volume | direction (1 = up) | period |
+#[derive(Debug)]
+pub struct Envelope {
+    pub volume: u8,
+    register: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            volume: 0,
+            register: 0,
+            timer: 0,
+        }
+    }
+
+    /// The DAC is off when the top 5 bits are all clear.
+    fn dac(&self) -> bool {
+        self.register & 0b1111_1000 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0b111;
+    }
+
+    fn clock(&mut self) {
+        let period = self.register & 0b111;
+        if period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if self.register & 0b1000 != 0 {
+                self.volume = (self.volume + 1).min(15);
+            } else {
+                self.volume = self.volume.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Periodically changes the frequency of the first pulse channel.
+///
+/// NR10:
+///   | 7 | 6-4    | 3                   | 2-0   |
+///   | - | period | direction (1 = down) | shift |
+#[derive(Debug)]
+pub struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+    // subtracting since the last trigger makes clearing the direction bit
+    // turn the channel off
+    negated: bool,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+            negated: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0b111
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    /// Next frequency, anything above 2047 turns the channel off.
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.register & 0b1000 != 0 {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn reload(&mut self) {
+        self.timer = match self.period() {
+            0 => 8,
+            p => p,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct Pulse {
+    pub enabled: bool,
+    pub length: Length,
+    pub envelope: Envelope,
+    // only the first pulse channel has a sweep unit
+    pub sweep: Option<Sweep>,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+}
+
+impl Pulse {
+    pub fn new(sweep: bool) -> Pulse {
+        Pulse {
+            enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep: sweep.then(Sweep::new),
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 8192,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Current amplitude (0-15).
+    pub fn output(&self) -> u8 {
+        if self.enabled && DUTY[self.duty as usize] >> (7 - self.duty_step) & 1 != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    pub fn dac(&self) -> bool {
+        self.envelope.dac()
+    }
+
+    /// Write one of NRx0-NRx4, `idx` being the x0-x4 part.
+    pub fn write(&mut self, idx: usize, val: u8, odd_step: bool) {
+        match idx {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.register = val;
+                    if sweep.negated && val & 0b1000 == 0 {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0b0011_1111);
+            }
+            2 => {
+                self.envelope.register = val;
+                self.enabled &= self.dac();
+            }
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0b111) << 8);
+                if !self.length.write(val, odd_step) {
+                    self.enabled = false;
+                }
+                if val & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.negated = false;
+            sweep.reload();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+            if sweep.shift() != 0 && sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+        let frequency = sweep.calculate();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.shift() != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // the new frequency is checked for overflow straight away
+            if sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Turning the APU off resets everything but the length counter.
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Pulse::new(self.sweep.is_some());
+        self.length.counter = length.counter;
+    }
+}
+
+/// Plays back the 32 4-bit samples stored in wave RAM.
+///
+/// NR32:
+///   | 7 | 6-5                                     | 4-0 |
+///   | - | volume: 00=mute 01=100% 10=50% 11=25% | -   |
+#[derive(Debug)]
+pub struct Wave {
+    pub enabled: bool,
+    pub length: Length,
+    pub ram: [u8; 16],
+    dac: bool,
+    volume: u8,
+    position: u8,
+    frequency: u16,
+    timer: u32,
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            length: Length::new(256),
+            ram: [0; 16],
+            dac: false,
+            volume: 0,
+            position: 0,
+            frequency: 0,
+            timer: 4096,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.volume == 0 {
+            return 0;
+        }
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0xF
+        };
+        sample >> (self.volume - 1)
+    }
+
+    pub fn dac(&self) -> bool {
+        self.dac
+    }
+
+    /// While the channel plays, wave RAM accesses reach the byte being played
+    /// instead of the one addressed.
+    pub fn ram_index(&self, idx: usize) -> usize {
+        if self.enabled {
+            self.position as usize / 2
+        } else {
+            idx
+        }
+    }
+
+    pub fn write(&mut self, idx: usize, val: u8, odd_step: bool) {
+        match idx {
+            0 => {
+                self.dac = val & 0b1000_0000 != 0;
+                self.enabled &= self.dac;
+            }
+            1 => self.length.load(val),
+            2 => self.volume = (val >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0b111) << 8);
+                if !self.length.write(val, odd_step) {
+                    self.enabled = false;
+                }
+                if val & 0b1000_0000 != 0 {
+                    self.enabled = self.dac;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn power_off(&mut self) {
+        let (ram, counter) = (self.ram, self.length.counter);
+        *self = Wave::new();
+        self.ram = ram;
+        self.length.counter = counter;
+    }
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Wave::new()
+    }
+}
+
+/// Pseudo random noise from a linear feedback shift register.
+///
+/// NR43:
+///   | 7-4   | 3                       | 2-0     |
+///   | shift | width (1 = 7 bit LFSR) | divisor |
+#[derive(Debug)]
+pub struct Noise {
+    pub enabled: bool,
+    pub length: Length,
+    pub envelope: Envelope,
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            register: 0,
+            lfsr: 0x7FFF,
+            timer: 8,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.register as usize & 0b111] << (self.register >> 4)
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.register & 0b1000 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    pub fn dac(&self) -> bool {
+        self.envelope.dac()
+    }
+
+    pub fn write(&mut self, idx: usize, val: u8, odd_step: bool) {
+        match idx {
+            1 => self.length.load(val & 0b0011_1111),
+            2 => {
+                self.envelope.register = val;
+                self.enabled &= self.dac();
+            }
+            3 => self.register = val,
+            4 => {
+                if !self.length.write(val, odd_step) {
+                    self.enabled = false;
+                }
+                if val & 0b1000_0000 != 0 {
+                    self.enabled = self.dac();
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn power_off(&mut self) {
+        let counter = self.length.counter;
+        *self = Noise::new();
+        self.length.counter = counter;
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::new()
+    }
+}
+
+impl Snapshot for Length {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        w.u16(self.counter);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        self.counter = r.u16()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&[self.volume, self.register, self.timer]);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        let mut v = [0; 3];
+        r.bytes(&mut v)?;
+        [self.volume, self.register, self.timer] = v;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.register);
+        w.bool(self.enabled);
+        w.u16(self.shadow);
+        w.u8(self.timer);
+        w.bool(self.negated);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.register = r.u8()?;
+        self.enabled = r.bool()?;
+        self.shadow = r.u16()?;
+        self.timer = r.u8()?;
+        self.negated = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        self.length.save(w);
+        self.envelope.save(w);
+        if let Some(sweep) = &self.sweep {
+            sweep.save(w);
+        }
+        w.u8(self.duty);
+        w.u8(self.duty_step);
+        w.u16(self.frequency);
+        w.u32(self.timer);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        Snapshot::load(&mut self.length, r)?;
+        self.envelope.load(r)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load(r)?;
+        }
+        self.duty = r.u8()? & 0b11;
+        self.duty_step = r.u8()? % 8;
+        self.frequency = r.u16()? & 0x7FF;
+        self.timer = r.u32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Wave {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        self.length.save(w);
+        w.bytes(&self.ram);
+        w.bool(self.dac);
+        w.u8(self.volume);
+        w.u8(self.position);
+        w.u16(self.frequency);
+        w.u32(self.timer);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        Snapshot::load(&mut self.length, r)?;
+        r.bytes(&mut self.ram)?;
+        self.dac = r.bool()?;
+        self.volume = r.u8()? & 0b11;
+        self.position = r.u8()? % 32;
+        self.frequency = r.u16()? & 0x7FF;
+        self.timer = r.u32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Noise {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        self.length.save(w);
+        self.envelope.save(w);
+        w.u8(self.register);
+        w.u16(self.lfsr);
+        w.u32(self.timer);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        Snapshot::load(&mut self.length, r)?;
+        self.envelope.load(r)?;
+        self.register = r.u8()?;
+        self.lfsr = r.u16()?;
+        self.timer = r.u32()?;
+        Ok(())
+    }
+}
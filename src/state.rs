@@ -0,0 +1,229 @@
+use std::fmt;
+use std::io;
+
+// every save state starts with these bytes
+const MAGIC: [u8; 4] = *b"THEY";
+/// Version of the save state layout, bumped whenever a component changes what
+/// it saves so old states get rejected instead of misread.
+pub const VERSION: u16 = 7;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    // not a save state at all
+    Magic,
+    // saved by an incompatible version
+    Version(u16),
+    // saved while running a different ROM
+    Rom,
+    // the data ended early
+    Truncated,
+    // a value that can't be restored, like a buffer of the wrong size
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{e}"),
+            StateError::Magic => write!(f, "not a save state"),
+            StateError::Version(v) => {
+                write!(
+                    f,
+                    "save state version {v} isn't supported, expected {VERSION}"
+                )
+            }
+            StateError::Rom => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+/// Components that can be written to and restored from a save state. Values
+/// are stored little endian in the order `save` writes them, with no field
+/// names or padding, so `load` has to read them back in the same order.
+pub trait Snapshot {
+    fn save(&self, w: &mut Writer);
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError>;
+}
+
+/// Builds up a save state.
+#[derive(Debug)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    /// Start a save state with the header identifying the format and the ROM.
+    pub fn new(rom_id: u32) -> Writer {
+        let mut w = Writer { data: Vec::new() };
+        w.bytes(&MAGIC);
+        w.u16(VERSION);
+        w.u32(rom_id);
+        w
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    /// Fixed size data, the reader has to know the length.
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.data.extend_from_slice(v);
+    }
+
+    /// Variable size data, prefixed by its length.
+    pub fn vec(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+}
+
+/// Reads a save state back in the order it was written.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Check the header, refusing states of another version or ROM.
+    pub fn new(data: &'a [u8], rom_id: u32) -> Result<Reader<'a>, StateError> {
+        let mut r = Reader { data };
+        let mut magic = [0; 4];
+        r.bytes(&mut magic).map_err(|_| StateError::Magic)?;
+        if magic != MAGIC {
+            return Err(StateError::Magic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(StateError::Version(version));
+        }
+        if r.u32()? != rom_id {
+            return Err(StateError::Rom);
+        }
+        Ok(r)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, v: &mut [u8]) -> Result<(), StateError> {
+        v.copy_from_slice(self.take(v.len())?);
+        Ok(())
+    }
+
+    /// Fill a buffer from length prefixed data of the same length.
+    pub fn vec(&mut self, v: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        if self.u32()? as usize != v.len() {
+            return Err(StateError::Invalid(what));
+        }
+        self.bytes(v)
+    }
+
+    /// Length prefixed data of any length.
+    pub fn vec_any(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Fail if anything is left over, the state came from a different layout.
+    pub fn end(&self) -> Result<(), StateError> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Invalid("length")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let mut w = Writer::new(0xABCD);
+        w.u8(1);
+        w.bool(true);
+        w.u16(0x1234);
+        w.u64(u64::MAX);
+        w.vec(&[1, 2, 3]);
+        let data = w.finish();
+
+        let mut r = Reader::new(&data, 0xABCD).unwrap();
+        assert_eq!(r.u8().unwrap(), 1);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0x1234);
+        assert_eq!(r.u64().unwrap(), u64::MAX);
+        let mut v = [0; 3];
+        r.vec(&mut v, "vec").unwrap();
+        assert_eq!(v, [1, 2, 3]);
+        r.end().unwrap();
+        assert!(matches!(r.u8(), Err(StateError::Truncated)));
+    }
+
+    #[test]
+    fn header_test() {
+        let data = Writer::new(1).finish();
+        assert!(matches!(Reader::new(&data, 2), Err(StateError::Rom)));
+        assert!(matches!(Reader::new(b"nope", 1), Err(StateError::Magic)));
+        let mut data = data;
+        data[4] = 0xFF;
+        assert!(matches!(Reader::new(&data, 1), Err(StateError::Version(_))));
+    }
+}
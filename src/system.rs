@@ -1,4 +1,50 @@
-use crate::{BootParameters, cartridge::Cartridge, cpu::CPU, ppu::Display, sound::Voices};
+use crate::{
+    BootParameters, CPU_HZ, FRAME_CYCLES, Hardware, PPU_HZ,
+    cartridge::{
+        Cartridge,
+        header::{CartridgeError, CgbFlag},
+    },
+    cpu::{CPU, CpuError, Registers},
+    ppu::Display,
+    state::{Reader, Snapshot, StateError, Writer},
+    trace::Tracer,
+};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// a machine cycle is made up of four clock ticks (T-cycles)
+const TICKS_PER_M_CYCLE: u32 = 4;
+
+#[derive(Debug)]
+pub enum SystemError {
+    Cartridge(CartridgeError),
+    BootRom(io::Error),
+    // the boot ROM isn't the size of the model's
+    BootRomSize { expected: usize, actual: usize },
+}
+
+impl fmt::Display for SystemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemError::Cartridge(e) => write!(f, "{e}"),
+            SystemError::BootRom(e) => write!(f, "can't read the boot ROM: {e}"),
+            SystemError::BootRomSize { expected, actual } => write!(
+                f,
+                "boot ROM is {actual} bytes but the model's is {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SystemError {}
+
+impl From<CartridgeError> for SystemError {
+    fn from(e: CartridgeError) -> Self {
+        SystemError::Cartridge(e)
+    }
+}
 
 /// The _system_ controls all the coordination involved between the disparate
 /// hardware components.
@@ -7,42 +53,428 @@ use crate::{BootParameters, cartridge::Cartridge, cpu::CPU, ppu::Display, sound:
 ///   1. CPU - Set Registers
 ///   2. RAM - Set Hardware Adress Registers
 ///   3. PPU - These are all set by reading from RAM
+///
+/// The CPU drives the clock, every instruction reports how many machine cycles
+/// it took and the rest of the components are advanced by the same amount of
+/// time so everything stays in lockstep.
 #[derive(Debug)]
 pub struct System {
     pub cpu: CPU,
     pub display: Display,
-    pub sound: Voices,
-    pub cartridge: Cartridge,
+    // total T-cycles elapsed since power on
+    pub cycles: u64,
+    // T-cycles elapsed within the current frame
+    frame_cycles: u32,
+    pub params: BootParameters,
+    // the model from the parameters, or the one the cartridge asks for
+    pub model: Hardware,
+    // every executed instruction is logged here when tracing
+    pub trace: Option<Tracer>,
 }
 
 impl System {
-    pub fn new(boot_params: BootParameters) -> System {
-        System {
-            cpu: CPU::new(),
+    /// Power up with the cartridge from the boot parameters inserted, failing
+    /// when the ROM can't be read or its header is invalid, or when the boot
+    /// ROM doesn't belong to the model.
+    pub fn new(boot_params: BootParameters) -> Result<System, SystemError> {
+        let mut cpu = CPU::new();
+        let cartridge = Cartridge::new(&boot_params.rom_path, &boot_params.save_path("sav"))?;
+        // without a model CGB cartridges get a CGB and the rest a DMG
+        let model = boot_params.model.unwrap_or(match cartridge.header.cgb {
+            CgbFlag::Dmg => Hardware::Dmg,
+            CgbFlag::Supported | CgbFlag::Only => Hardware::Cgb,
+        });
+        cpu.illegal_policy = boot_params.illegal;
+        if let Some(path) = &boot_params.boot_rom {
+            let rom = fs::read(path).map_err(SystemError::BootRom)?;
+            let expected = model.boot_rom_size();
+            if rom.len() != expected {
+                return Err(SystemError::BootRomSize {
+                    expected,
+                    actual: rom.len(),
+                });
+            }
+            cpu.memory.boot_rom = rom;
+        }
+        // CGB cartridges run in CGB mode, the rest in DMG compatibility
+        cpu.memory.cgb = model == Hardware::Cgb && cartridge.header.cgb != CgbFlag::Dmg;
+        cpu.memory.cartridge = Some(cartridge);
+        Ok(System {
+            cpu,
             display: Display::new(false),
-            sound: Voices::new(),
-            cartridge: Cartridge::new(&boot_params.rom_path),
+            cycles: 0,
+            frame_cycles: 0,
+            params: boot_params,
+            model,
+            trace: None,
+        })
+    }
+
+    /// Execute a single instruction and advance every other component by the
+    /// same amount of time. Returns the T-cycles that elapsed, at double speed
+    /// an instruction takes half as long. Fails on an illegal opcode unless
+    /// the CPU is set to lock up on them, nothing advances then.
+    pub fn step(&mut self) -> Result<u32, CpuError> {
+        if let Some(trace) = &mut self.trace {
+            // a failed write shouldn't stop the emulator, the log is only
+            // for debugging
+            trace.trace(&mut self.cpu).ok();
+        }
+        let mut cycles = self.cpu.exec()? as u32 * TICKS_PER_M_CYCLE;
+        if self.cpu.memory.double_speed {
+            cycles /= 2;
+        }
+        // VRAM DMA holds up the CPU while the rest keeps going
+        cycles += self.cpu.memory.take_stall();
+        self.tick(cycles);
+        Ok(cycles)
+    }
+
+    /// Run instructions until a full frame worth of time has elapsed.
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Like `run_frame` but checks `stop` after every instruction, leaving the
+    /// rest of the frame for the next call once it returns true. Returns
+    /// whether the frame finished.
+    pub fn run_frame_until(
+        &mut self,
+        mut stop: impl FnMut(&mut System) -> bool,
+    ) -> Result<bool, CpuError> {
+        while self.frame_cycles < FRAME_CYCLES {
+            self.step()?;
+            if stop(self) {
+                return Ok(false);
+            }
+        }
+        self.frame_cycles -= FRAME_CYCLES;
+        Ok(true)
+    }
+
+    /// Advance every component besides the CPU by the given T-cycles.
+    fn tick(&mut self, cycles: u32) {
+        // the PPU is clocked independently from the CPU
+        let dots = (cycles as u64 * PPU_HZ as u64 / CPU_HZ as u64) as u32;
+        self.cpu.memory.tick(cycles);
+        self.display.tick(&mut self.cpu.memory, dots);
+        self.cycles += cycles as u64;
+        self.frame_cycles += cycles;
+    }
+
+    /// Log every instruction to a file from now on, replacing it.
+    pub fn trace_to(&mut self, path: &Path) -> io::Result<()> {
+        self.trace = Some(Tracer::create(path)?);
+        Ok(())
+    }
+
+    /// Write battery backed cartridge RAM to its `.sav` file if it changed, and
+    /// whatever of the trace is still buffered.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(trace) = &mut self.trace {
+            trace.flush()?;
+        }
+        match &mut self.cpu.memory.cartridge {
+            Some(cartridge) => cartridge.flush(),
+            None => Ok(()),
         }
     }
 
-    /// Starts the instruction loop.
-    pub fn run(&mut self) {
-        loop {
-            print!("<< {} >> ", self.cpu.registers);
-            self.cpu.exec();
-            let character = self.cpu.memory.read_byte(0xFF01);
-            print!(" -- Char: {:2x} -- ", character);
+    fn rom_id(&self) -> u32 {
+        self.cpu.memory.cartridge.as_ref().map_or(0, |c| c.id())
+    }
+
+    /// Snapshot of the whole machine, see `state` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer::new(self.rom_id());
+        self.save(&mut w);
+        w.finish()
+    }
+
+    /// Restore a snapshot taken with `save_state` of the same ROM. A state
+    /// that can't be restored leaves the system as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader::new(data, self.rom_id())?;
+        let backup = self.save_state();
+        let result = self.load(&mut r).and_then(|_| r.end());
+        if result.is_err() {
+            let mut r = Reader::new(&backup, self.rom_id())?;
+            self.load(&mut r)?;
         }
+        result
+    }
+
+    /// File of a numbered save state slot, `game.gb` keeps slot 1 in
+    /// `game.ss1`.
+    pub fn slot_path(&self, slot: u8) -> PathBuf {
+        self.params.save_path(&format!("ss{slot}"))
+    }
+
+    pub fn save_slot(&self, slot: u8) -> Result<(), StateError> {
+        fs::write(self.slot_path(slot), self.save_state())?;
+        Ok(())
+    }
+
+    pub fn load_slot(&mut self, slot: u8) -> Result<(), StateError> {
+        let data = fs::read(self.slot_path(slot))?;
+        self.load_state(&data)
     }
 
-    /// Initialize the system like loading the game's cartridge rom into system memory.
+    /// Initialize the system to the state it's in after powering on, the
+    /// cartridge is mapped into memory through its memory bank controller.
+    /// With a boot ROM it runs from 0x0000 and sets up the hardware itself,
+    /// otherwise everything starts out the way the model's boot ROM would
+    /// leave it.
     pub fn initialize(&mut self) {
-        self.load_rom_bank(0, 1 << 15);
+        self.cpu.lockup = None;
+        if !self.cpu.memory.boot_rom.is_empty() {
+            self.cpu.registers = Registers::power_on();
+            self.cpu.memory.power_on();
+            return;
+        }
+        let header = self.cpu.memory.cartridge.as_ref().map(|c| &c.header);
+        self.cpu.registers = Registers::post_boot(self.model, header);
+        self.cpu.memory.initialize(self.model);
+    }
+}
+
+impl Snapshot for System {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.cycles);
+        w.u32(self.frame_cycles);
+        self.cpu.save(w);
+        self.display.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.cycles = r.u64()?;
+        self.frame_cycles = r.u32()?;
+        self.cpu.load(r)?;
+        self.display.load(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::IllegalOpcodePolicy;
+    use crate::ppu::Mode;
+    use crate::ram::MemoryRegister;
+
+    fn setup() -> System {
+        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
+        let params = BootParameters {
+            model: Some(Hardware::Dmg),
+            ..BootParameters::new(&p)
+        };
+        let mut system = System::new(params).unwrap();
+        system.initialize();
+        system
     }
 
-    fn load_rom_bank(&mut self, low: u16, high: u16) {
-        for (mem_idx, rom_idx) in (low..high).enumerate() {
-            self.cpu.memory.ram[mem_idx] = self.cartridge.rom[rom_idx as usize];
+    #[test]
+    fn lockstep_test() {
+        let mut system = setup();
+        let counter = system.cpu.memory.timer.counter;
+        let cycles = system.step().unwrap();
+        assert_eq!(system.cycles, cycles as u64);
+        assert_eq!(system.display.dots, cycles);
+        assert_eq!(system.cpu.memory.timer.counter, counter + cycles as u16);
+    }
+
+    #[test]
+    fn save_state_test() {
+        let mut system = setup();
+        for _ in 0..10 {
+            system.run_frame().unwrap();
+        }
+        let state = system.save_state();
+        for _ in 0..10 {
+            system.run_frame().unwrap();
+        }
+        let expected = system.save_state();
+
+        // running the same frames from the snapshot ends up in the same state
+        system.load_state(&state).unwrap();
+        assert_eq!(system.save_state(), state);
+        for _ in 0..10 {
+            system.run_frame().unwrap();
         }
+        assert_eq!(system.save_state(), expected);
+    }
+
+    #[test]
+    fn bad_state_test() {
+        let mut system = setup();
+        let state = system.save_state();
+        system.run_frame().unwrap();
+        let current = system.save_state();
+        assert!(matches!(
+            system.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+        assert_eq!(system.save_state(), current);
+    }
+
+    #[test]
+    fn post_boot_test() {
+        let mut system = setup();
+        let r = &system.cpu.registers;
+        assert_eq!((r.acc, r.flags, r.pc, r.sp), (0x01, 0xB0, 0x100, 0xFFFE));
+        assert_eq!(system.cpu.memory.read_byte(0xFF04), 0xAB);
+        assert_eq!(system.cpu.memory.read_byte(0xFF40), 0x91);
+
+        system.model = Hardware::Cgb;
+        system.initialize();
+        let r = &system.cpu.registers;
+        // cpu_instrs is marked as supporting the CGB
+        assert_eq!(
+            (r.acc, r.d, r.e, r.high, r.low),
+            (0x11, 0xFF, 0x56, 0x00, 0x0D)
+        );
+    }
+
+    #[test]
+    fn cgb_header_test() {
+        let path = std::env::temp_dir().join(format!("they-cgb-{}.gbc", std::process::id()));
+        let mut rom = crate::cartridge::header::tests::rom();
+        rom[0x143] = 0xC0;
+        crate::cartridge::header::tests::fix_checksum(&mut rom);
+        fs::write(&path, &rom).unwrap();
+        // a CGB only cartridge picks the CGB without a model
+        let system = System::new(BootParameters::new(&path)).unwrap();
+        assert_eq!(system.model, Hardware::Cgb);
+        assert!(system.cpu.memory.cgb);
+
+        let params = BootParameters {
+            model: Some(Hardware::Dmg),
+            ..BootParameters::new(&path)
+        };
+        let system = System::new(params).unwrap();
+        assert!(!system.cpu.memory.cgb);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn double_speed_test() {
+        let mut system = setup();
+        system.cpu.memory.cgb = true;
+        // prepare the switch and stop
+        system.cpu.memory.register_write(MemoryRegister::KEY1, 1);
+        assert_eq!(system.cpu.memory.register_read(MemoryRegister::KEY1), 0x7F);
+        let pc = system.cpu.registers.pc;
+        system.cpu.memory.cartridge = None;
+        system.cpu.memory.write_byte(pc, 0x10);
+        system.cpu.memory.write_byte(pc + 1, 0x00);
+        system.step().unwrap();
+        assert_eq!(system.cpu.registers.pc, pc + 2);
+        assert!(system.cpu.memory.double_speed);
+        assert!(!system.cpu.stop);
+        assert_eq!(system.cpu.memory.register_read(MemoryRegister::KEY1), 0xFE);
+
+        // a NOP takes half the time, the timer counts a full machine cycle
+        let counter = system.cpu.memory.timer.counter;
+        let dots = system.display.dots;
+        assert_eq!(system.step(), Ok(2));
+        assert_eq!(system.display.dots, dots + 2);
+        assert_eq!(system.cpu.memory.timer.counter, counter + 4);
+    }
+
+    #[test]
+    fn trace_test() {
+        let path = std::env::temp_dir().join(format!("they-trace-{}.log", std::process::id()));
+        let mut system = setup();
+        system.trace_to(&path).unwrap();
+        for _ in 0..3 {
+            system.step().unwrap();
+        }
+        system.flush().unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+        assert!(lines[1].contains("PC:0101"));
+        assert!(lines[2].contains("PC:0213"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn boot_rom_test() {
+        let path = std::env::temp_dir().join(format!("they-boot-{}.bin", std::process::id()));
+        // slide through NOPs and unmap the boot ROM with its last instruction,
+        // `ld a, 1; ldh (0x50), a`
+        let mut boot = vec![0; 0x100];
+        boot[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        fs::write(&path, &boot).unwrap();
+        let rom = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
+        let mut params = BootParameters {
+            model: Some(Hardware::Dmg),
+            ..BootParameters::new(&rom)
+        };
+        params.boot_rom = Some(path.clone());
+        let mut system = System::new(params.clone()).unwrap();
+        system.initialize();
+        assert_eq!(system.cpu.registers.pc, 0);
+        assert_eq!(system.cpu.memory.read_byte(0xFC), 0x3E);
+        while system.cpu.registers.pc != 0x100 {
+            system.step().unwrap();
+        }
+        assert!(!system.cpu.memory.boot_mapped);
+        let cartridge = system.cpu.memory.cartridge.as_ref().unwrap().read(0xFC);
+        assert_eq!(system.cpu.memory.read_byte(0xFC), cartridge);
+
+        params.model = Some(Hardware::Cgb);
+        assert!(matches!(
+            System::new(params),
+            Err(SystemError::BootRomSize {
+                expected: 0x900,
+                actual: 0x100
+            })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn illegal_opcode_test() {
+        let mut system = setup();
+        system.cpu.memory.cartridge = None;
+        system.cpu.memory.write_byte(0x100, 0xD3);
+        let error = CpuError::IllegalOpcode {
+            opcode: 0xD3,
+            addr: 0x100,
+        };
+
+        // locked up the CPU idles until reset, while the rest keeps going
+        system.step().unwrap();
+        assert_eq!(system.cpu.lockup, Some(error));
+        assert_eq!(system.step(), Ok(4));
+        assert_eq!(system.cpu.registers.pc, 0x101);
+        let state = system.save_state();
+        system.initialize();
+        assert_eq!(system.cpu.lockup, None);
+        system.load_state(&state).unwrap();
+        assert_eq!(system.cpu.lockup, Some(error));
+
+        system.initialize();
+        system.cpu.illegal_policy = IllegalOpcodePolicy::Error;
+        let cycles = system.cycles;
+        assert_eq!(system.run_frame(), Err(error));
+        assert_eq!(system.cpu.registers.pc, 0x100);
+        assert_eq!(system.cycles, cycles);
+        assert_eq!(system.cpu.lockup, None);
+    }
+
+    #[test]
+    fn run_frame_test() {
+        let mut system = setup();
+        system.run_frame().unwrap();
+        assert!(system.cycles >= FRAME_CYCLES as u64);
+        assert!(system.cycles < FRAME_CYCLES as u64 + 24);
+        assert_eq!(system.display.mode, Mode::OamScan);
     }
 }
@@ -0,0 +1,195 @@
+use crate::ram::MemoryRegister;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// The timer is made of a free running divider (DIV) and a programmable
+/// counter (TIMA). DIV is the upper byte of a 16-bit counter that is
+/// incremented every T-cycle. TIMA is incremented whenever the counter bit
+/// selected by TAC, ANDed with the enable bit, goes from 1 to 0. This means
+/// resetting DIV or changing TAC can increment TIMA as well.
+///
+/// When TIMA overflows it reads as 0 for one M-cycle, after which it's
+/// reloaded from TMA and the timer interrupt is requested. Writing TIMA during
+/// that cycle cancels the reload.
+///
+/// TAC:
+///   | 7-3    | 2      | 1-0                                     |
+///   | unused | enable | clock select: 00=4096 01=262144 10=65536 11=16384 Hz |
+#[derive(Debug)]
+pub struct Timer {
+    // internal counter, DIV is the upper 8 bits
+    pub counter: u16,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    // T-cycles left until TIMA is reloaded after an overflow, 0 when idle
+    reload: u8,
+}
+
+// T-cycles between a TIMA overflow and its reload
+const RELOAD_DELAY: u8 = 4;
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: 0,
+        }
+    }
+
+    /// Counter bit watched for the selected clock.
+    fn bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    /// Input of the falling edge detector driving TIMA.
+    fn signal(&self) -> bool {
+        self.tac & 0b100 != 0 && self.counter & self.bit() != 0
+    }
+
+    fn increment(&mut self) {
+        let (v, carry) = self.tima.overflowing_add(1);
+        self.tima = v;
+        if carry {
+            self.reload = RELOAD_DELAY;
+        }
+    }
+
+    /// Run `change` and increment TIMA if it caused a falling edge.
+    fn edge(&mut self, change: impl FnOnce(&mut Timer)) {
+        let before = self.signal();
+        change(self);
+        if before && !self.signal() {
+            self.increment();
+        }
+    }
+
+    /// Advance the timer by the given amount of T-cycles and report whether
+    /// the timer interrupt should be requested.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles {
+            if self.reload > 0 {
+                self.reload -= 1;
+                if self.reload == 0 {
+                    self.tima = self.tma;
+                    interrupt = true;
+                }
+            }
+            self.edge(|t| t.counter = t.counter.wrapping_add(1));
+        }
+        interrupt
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            a if a == MemoryRegister::DIV as u16 => (self.counter >> 8) as u8,
+            a if a == MemoryRegister::TIMA as u16 => self.tima,
+            a if a == MemoryRegister::TMA as u16 => self.tma,
+            // unused bits read back as set
+            _ => self.tac | 0b1111_1000,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // any write resets the whole divider
+            a if a == MemoryRegister::DIV as u16 => self.edge(|t| t.counter = 0),
+            a if a == MemoryRegister::TIMA as u16 => {
+                self.reload = 0;
+                self.tima = val;
+            }
+            a if a == MemoryRegister::TMA as u16 => self.tma = val,
+            _ => self.edge(|t| t.tac = val & 0b111),
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new()
+    }
+}
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.counter);
+        w.bytes(&[self.tima, self.tma, self.tac, self.reload]);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.counter = r.u16()?;
+        let mut v = [0; 4];
+        r.bytes(&mut v)?;
+        [self.tima, self.tma, self.tac, self.reload] = v;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_test() {
+        let mut t = Timer::new();
+        t.tick(255);
+        assert_eq!(t.read(MemoryRegister::DIV as u16), 0);
+        t.tick(1);
+        assert_eq!(t.read(MemoryRegister::DIV as u16), 1);
+        t.write(MemoryRegister::DIV as u16, 0x42);
+        assert_eq!(t.read(MemoryRegister::DIV as u16), 0);
+    }
+
+    #[test]
+    fn tima_overflow_test() {
+        let mut t = Timer::new();
+        t.write(MemoryRegister::TAC as u16, 0b101);
+        t.write(MemoryRegister::TMA as u16, 0xAB);
+        t.write(MemoryRegister::TIMA as u16, 0xFF);
+        assert!(!t.tick(15));
+        assert!(!t.tick(1));
+        // TIMA holds 0 for a cycle before the reload
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 0);
+        assert!(!t.tick(3));
+        assert!(t.tick(1));
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 0xAB);
+    }
+
+    #[test]
+    fn cancel_reload_test() {
+        let mut t = Timer::new();
+        t.write(MemoryRegister::TAC as u16, 0b101);
+        t.write(MemoryRegister::TIMA as u16, 0xFF);
+        t.tick(16);
+        t.write(MemoryRegister::TIMA as u16, 0x12);
+        assert!(!t.tick(4));
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 0x12);
+    }
+
+    #[test]
+    fn falling_edge_test() {
+        let mut t = Timer::new();
+        t.write(MemoryRegister::TAC as u16, 0b101);
+        // bit 3 is set, resetting DIV makes it fall
+        t.tick(8);
+        t.write(MemoryRegister::DIV as u16, 0);
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 1);
+        // as does disabling the timer
+        t.tick(8);
+        t.write(MemoryRegister::TAC as u16, 0b001);
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 2);
+        // while resetting DIV with the bit clear does nothing
+        t.tick(8);
+        t.write(MemoryRegister::TAC as u16, 0b101);
+        t.write(MemoryRegister::DIV as u16, 0);
+        assert_eq!(t.read(MemoryRegister::TIMA as u16), 2);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::CPU_HZ;
+use crate::cpu::{CPU, disassembler::disassemble};
+use crate::system::System;
+use flate2::bufread::MultiGzDecoder;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// steps a halted CPU gets to wake up before a diff gives up on it
+const HALT_STEPS: u32 = CPU_HZ;
+
+/// The state of the CPU before an instruction runs, in the format most
+/// emulators' reference logs use so they can be compared line by line:
+///
+///   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+///
+/// PCMEM is the four bytes from PC on, read without side effects.
+pub fn line(cpu: &mut CPU) -> String {
+    let r = &cpu.registers;
+    let (pc, sp) = (r.pc, r.sp);
+    let registers = format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+        r.acc, r.flags, r.b, r.c, r.d, r.e, r.high, r.low
+    );
+    let mem: Vec<String> = (0..4)
+        .map(|i| format!("{:02X}", cpu.memory.peek(pc.wrapping_add(i))))
+        .collect();
+    format!(
+        "{registers} SP:{sp:04X} PC:{pc:04X} PCMEM:{}",
+        mem.join(",")
+    )
+}
+
+/// Writes a `line` to a file for every instruction executed, nothing is
+/// logged while the CPU is halted or locked up.
+#[derive(Debug)]
+pub struct Tracer {
+    out: BufWriter<File>,
+}
+
+impl Tracer {
+    pub fn create(path: &Path) -> io::Result<Tracer> {
+        Ok(Tracer {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn trace(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        if cpu.halt || cpu.lockup.is_some() {
+            return Ok(());
+        }
+        writeln!(self.out, "{}", line(cpu))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Where a run first stopped matching a reference log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // line of the reference log, counting from 1
+    pub line: usize,
+    pub expected: String,
+    // what was logged instead, "halted" when the CPU never woke up or the
+    // error when it ran into an illegal opcode
+    pub actual: String,
+    // names of the fields that differ, like A or PC
+    pub fields: Vec<String>,
+    // reference lines leading up to the divergence, which all matched
+    pub context: Vec<String>,
+    // the last instruction run, which is usually the culprit
+    pub previous: Option<String>,
+    // the instruction about to run
+    pub next: String,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "diverged at line {}", self.line)?;
+        for line in &self.context {
+            writeln!(f, "  {line}")?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        writeln!(f, "+ {}", self.actual)?;
+        if !self.fields.is_empty() {
+            writeln!(f, "differs in {}", self.fields.join(", "))?;
+        }
+        if let Some(previous) = &self.previous {
+            writeln!(f, "after {previous}")?;
+        }
+        write!(f, "next  {}", self.next)
+    }
+}
+
+/// Open a reference log, gzip compressed ones are recognized by their magic
+/// number.
+pub fn open_log(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(match file.fill_buf()? {
+        [0x1F, 0x8B, ..] => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        _ => Box::new(file),
+    })
+}
+
+/// Run the system alongside a reference log until they differ or the log
+/// ends. Only the fields the reference has are compared, so logs without
+/// PCMEM or in a different order work as well. `context` is how many of the
+/// reference lines before a divergence to keep.
+pub fn diff<R: BufRead>(
+    system: &mut System,
+    log: R,
+    context: usize,
+) -> io::Result<Option<Divergence>> {
+    let mut recent = VecDeque::with_capacity(context + 1);
+    let mut previous = None;
+    // the step that failed, the next reference line diverges from it
+    let mut error = None;
+    for (i, expected) in log.lines().enumerate() {
+        let expected = expected?;
+        if expected.trim().is_empty() {
+            continue;
+        }
+        // halted steps aren't logged
+        let mut halted = 0;
+        while error.is_none() && system.cpu.halt && halted < HALT_STEPS {
+            error = system.step().err();
+            halted += 1;
+        }
+        let stuck = error.or(system.cpu.lockup);
+        let (actual, fields) = match (stuck, system.cpu.halt) {
+            (Some(e), _) => (e.to_string(), Vec::new()),
+            (None, true) => ("halted".to_owned(), Vec::new()),
+            (None, false) => {
+                let actual = line(&mut system.cpu);
+                let fields = mismatches(&expected, &actual);
+                (actual, fields)
+            }
+        };
+        let pc = system.cpu.registers.pc;
+        if stuck.is_some() || system.cpu.halt || !fields.is_empty() {
+            return Ok(Some(Divergence {
+                line: i + 1,
+                expected,
+                actual,
+                fields,
+                context: recent.into(),
+                previous: previous.map(|addr| listing(&mut system.cpu, addr)),
+                next: listing(&mut system.cpu, pc),
+            }));
+        }
+        recent.push_back(expected);
+        if recent.len() > context {
+            recent.pop_front();
+        }
+        previous = Some(pc);
+        error = system.step().err();
+    }
+    Ok(None)
+}
+
+/// Names of the fields of the reference line which the actual one doesn't
+/// have the same value for, ignoring case.
+fn mismatches(expected: &str, actual: &str) -> Vec<String> {
+    let actual: Vec<(&str, &str)> = actual
+        .split_whitespace()
+        .filter_map(|f| f.split_once(':'))
+        .collect();
+    expected
+        .split_whitespace()
+        .filter_map(|f| f.split_once(':'))
+        .filter(|(name, value)| {
+            actual
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .is_some_and(|(_, v)| !v.eq_ignore_ascii_case(value))
+        })
+        .map(|(name, _)| name.to_owned())
+        .collect()
+}
+
+fn listing(cpu: &mut CPU, addr: u16) -> String {
+    disassemble(addr, |a| cpu.memory.peek(a)).line(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BootParameters, Hardware};
+    use flate2::{Compression, write::GzEncoder};
+
+    #[test]
+    fn line_test() {
+        let mut cpu = CPU::new();
+        let r = &mut cpu.registers;
+        (r.acc, r.flags, r.b, r.c) = (0x01, 0xB0, 0x00, 0x13);
+        (r.d, r.e, r.high, r.low) = (0x00, 0xD8, 0x01, 0x4D);
+        (r.sp, r.pc) = (0xFFFE, 0xC000);
+        for (i, b) in [0x00, 0xC3, 0x13, 0x02].into_iter().enumerate() {
+            cpu.memory.write_byte(0xC000 + i as u16, b);
+        }
+        assert_eq!(
+            line(&mut cpu),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:C000 PCMEM:00,C3,13,02"
+        );
+    }
+
+    fn setup() -> System {
+        let p = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
+        let params = BootParameters {
+            model: Some(Hardware::Dmg),
+            ..BootParameters::new(&p)
+        };
+        let mut system = System::new(params).unwrap();
+        system.initialize();
+        system
+    }
+
+    /// Lines the system logs for its first instructions.
+    fn reference(lines: usize) -> Vec<String> {
+        let mut system = setup();
+        (0..lines)
+            .map(|_| {
+                let l = line(&mut system.cpu);
+                system.step().unwrap();
+                l
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_test() {
+        let reference = reference(100);
+        assert_eq!(
+            diff(&mut setup(), reference.join("\n").as_bytes(), 3).unwrap(),
+            None
+        );
+
+        // without PCMEM and with A off at line 50
+        let mut log: Vec<String> = reference
+            .iter()
+            .map(|l| l.split(" PCMEM").next().unwrap().to_owned())
+            .collect();
+        let wrong = log[49].replacen("A:", "A:1", 1).replacen(" F:", "0 F:", 1);
+        log[49] = wrong.clone();
+        let d = diff(&mut setup(), log.join("\n").as_bytes(), 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(d.line, 50);
+        assert_eq!(d.expected, wrong);
+        assert_eq!(d.actual, reference[49]);
+        assert_eq!(d.fields, ["A"]);
+        assert_eq!(d.context, log[46..49]);
+        let pc = &reference[49][reference[49].find("PC:").unwrap() + 3..][..4];
+        assert!(d.next.starts_with(&pc.to_ascii_lowercase()));
+        assert!(d.previous.is_some());
+    }
+
+    #[test]
+    fn gzip_test() {
+        let path = std::env::temp_dir().join(format!("they-trace-{}.log.gz", std::process::id()));
+        let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        for l in reference(20) {
+            writeln!(gz, "{l}").unwrap();
+        }
+        gz.finish().unwrap();
+        let log = open_log(&path).unwrap();
+        assert_eq!(diff(&mut setup(), log, 3).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,8 +3,8 @@ use they::cpu::{ALUFlag, CPU};
 fn setup(opcode: u8) -> CPU {
     let mut cpu = CPU::new();
 
-    cpu.memory.ram[0x0] = 0xCB;
-    cpu.memory.ram[0x1] = opcode;
+    cpu.memory.write_byte(0x0, 0xCB);
+    cpu.memory.write_byte(0x1, opcode);
     cpu.registers.acc = 1;
     cpu.registers.b = 2;
     cpu.registers.c = 2;
@@ -21,7 +21,7 @@ fn setup(opcode: u8) -> CPU {
 #[test]
 fn test_rl_r8() {
     let mut cpu = setup(0x10);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x4);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -33,10 +33,10 @@ fn test_rl_r8() {
 #[test]
 fn test_rl_hlm() {
     let mut cpu = setup(0x16);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xE1);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xE1);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -47,7 +47,7 @@ fn test_rl_hlm() {
 #[test]
 fn test_rr_r8() {
     let mut cpu = setup(0x18);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x1);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -56,7 +56,7 @@ fn test_rr_r8() {
     assert!(!cpu.check_flag(ALUFlag::C));
 
     cpu.registers.pc = 0;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x0);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -68,10 +68,10 @@ fn test_rr_r8() {
 #[test]
 fn test_rr_hlm() {
     let mut cpu = setup(0x1E);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xF8);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xF8);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -82,7 +82,7 @@ fn test_rr_hlm() {
 #[test]
 fn test_rlc_r8() {
     let mut cpu = setup(0x0);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x4);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -94,10 +94,10 @@ fn test_rlc_r8() {
 #[test]
 fn test_rlc_hlm() {
     let mut cpu = setup(0x6);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xE1);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xE1);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -108,7 +108,7 @@ fn test_rlc_hlm() {
 #[test]
 fn test_rrc_r8() {
     let mut cpu = setup(0x8);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x1);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -120,10 +120,10 @@ fn test_rrc_r8() {
 #[test]
 fn test_rrc_hlm() {
     let mut cpu = setup(0xE);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0x78);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x78);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -134,7 +134,7 @@ fn test_rrc_hlm() {
 #[test]
 fn test_sla_r8() {
     let mut cpu = setup(0x20);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x4);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -146,10 +146,10 @@ fn test_sla_r8() {
 #[test]
 fn test_sla_hlm() {
     let mut cpu = setup(0x26);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xE0);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xE0);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -161,7 +161,7 @@ fn test_sla_hlm() {
 fn test_sra_r8() {
     let mut cpu = setup(0x28);
     cpu.registers.b = 0x8F;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0xC7);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -173,10 +173,10 @@ fn test_sra_r8() {
 #[test]
 fn test_sra_hlm() {
     let mut cpu = setup(0x2E);
-    cpu.memory.ram[0x0100] = 0x0F;
+    cpu.memory.write_byte(0x0100, 0x0F);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0x7);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x7);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -188,7 +188,7 @@ fn test_sra_hlm() {
 fn test_srl_r8() {
     let mut cpu = setup(0x38);
     cpu.registers.b = 0x8F;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x47);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -200,10 +200,10 @@ fn test_srl_r8() {
 #[test]
 fn test_srl_hlm() {
     let mut cpu = setup(0x3E);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0x78);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x78);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -215,7 +215,7 @@ fn test_srl_hlm() {
 fn test_swap_r8() {
     let mut cpu = setup(0x30);
     cpu.registers.b = 0x8F;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0xF8);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -227,10 +227,10 @@ fn test_swap_r8() {
 #[test]
 fn test_swap_hlm() {
     let mut cpu = setup(0x36);
-    cpu.memory.ram[0x0100] = 0xF0;
+    cpu.memory.write_byte(0x0100, 0xF0);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0x0F);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x0F);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
@@ -242,7 +242,7 @@ fn test_swap_hlm() {
 fn test_bit_r8() {
     let mut cpu = setup(0x40);
     cpu.registers.b = 0x0;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x0);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -254,9 +254,9 @@ fn test_bit_r8() {
 #[test]
 fn test_bit_hlm() {
     let mut cpu = setup(0x46);
-    cpu.memory.ram[0x0100] = 0xFF;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xFF);
+    cpu.memory.write_byte(0x0100, 0xFF);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xFF);
     assert_eq!(cpu.registers.pc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(cpu.check_flag(ALUFlag::H));
@@ -268,7 +268,7 @@ fn test_bit_hlm() {
 fn test_res_r8() {
     let mut cpu = setup(0x80);
     cpu.registers.b = 0x1;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x0);
     assert_eq!(cpu.registers.pc, 0x2);
 }
@@ -276,9 +276,9 @@ fn test_res_r8() {
 #[test]
 fn test_res_hlm() {
     let mut cpu = setup(0x86);
-    cpu.memory.ram[0x0100] = 0xFF;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0xFE);
+    cpu.memory.write_byte(0x0100, 0xFF);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xFE);
     assert_eq!(cpu.registers.pc, 0x2);
 }
 
@@ -286,7 +286,7 @@ fn test_res_hlm() {
 fn test_set_r8() {
     let mut cpu = setup(0xC0);
     cpu.registers.b = 0x0;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x1);
     assert_eq!(cpu.registers.pc, 0x2);
 }
@@ -294,8 +294,8 @@ fn test_set_r8() {
 #[test]
 fn test_set_hlm() {
     let mut cpu = setup(0xC6);
-    cpu.memory.ram[0x0100] = 0x0;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0100], 0x1);
+    cpu.memory.write_byte(0x0100, 0x0);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x1);
     assert_eq!(cpu.registers.pc, 0x2);
 }
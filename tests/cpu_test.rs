@@ -3,7 +3,7 @@ use they::cpu::{ALUFlag, CPU};
 fn setup(opcode: u8) -> CPU {
     let mut cpu = CPU::new();
 
-    cpu.memory.ram[0x0] = opcode;
+    cpu.memory.write_byte(0x0, opcode);
     cpu.registers.acc = 1;
     cpu.registers.b = 2;
     cpu.registers.c = 2;
@@ -14,7 +14,7 @@ fn setup(opcode: u8) -> CPU {
     cpu.registers.low = 0;
     cpu.registers.pc = 0;
     cpu.registers.sp = 0;
-    cpu.memory.ram[0x0202] = 0xAA;
+    cpu.memory.write_byte(0x0202, 0xAA);
     cpu
 }
 
@@ -22,7 +22,7 @@ fn setup(opcode: u8) -> CPU {
 fn test_nop() {
     // opcode for nop
     let mut cpu = setup(0x0);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert_eq!(cpu.registers.b, 0x2);
@@ -32,17 +32,17 @@ fn test_nop() {
     assert_eq!(cpu.registers.flags, 0x0);
     assert_eq!(cpu.registers.high, 0x1);
     assert_eq!(cpu.registers.low, 0x0);
-    assert_eq!(cpu.memory.ram[0x0], 0x0);
+    assert_eq!(cpu.memory.read_byte(0x0), 0x0);
 }
 
 #[test]
 fn test_ld_r16_n16() {
     // opcode where r16 is registers(b,c)
     let mut cpu = setup(0x1);
-    cpu.memory.ram[0x1] = 0xFF;
-    cpu.memory.ram[0x2] = 0xEE;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x1);
+    cpu.memory.write_byte(0x1, 0xFF);
+    cpu.memory.write_byte(0x2, 0xEE);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x1);
     assert_eq!(cpu.registers.pc, 0x3);
     assert_eq!(cpu.registers.c, 0xFF);
     assert_eq!(cpu.registers.b, 0xEE);
@@ -52,19 +52,19 @@ fn test_ld_r16_n16() {
 fn test_ld_r16m_a() {
     // opcode where r16 is registers(b,c)
     let mut cpu = setup(0x2);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x2);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x2);
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert_eq!(cpu.registers.b, 0x2);
     assert_eq!(cpu.registers.c, 0x2);
-    assert_eq!(cpu.memory.ram[0x0202], 0x1);
+    assert_eq!(cpu.memory.read_byte(0x0202), 0x1);
 }
 
 #[test]
 fn test_inc_sp() {
     let mut cpu = setup(0x33);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.sp, 0x1);
 }
@@ -73,8 +73,8 @@ fn test_inc_sp() {
 fn test_inc_r16() {
     // specific opcode for registers (b, c)
     let mut cpu = setup(0x3);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x3);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x3);
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert_eq!(cpu.registers.b, 0x2);
@@ -86,8 +86,8 @@ fn test_inc_r16() {
 fn test_inc_r8() {
     // specific opcode for register b
     let mut cpu = setup(0x4);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x4);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x4);
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.b, 0x3);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -98,12 +98,13 @@ fn test_inc_r8() {
     // check flags on overflow
     cpu.registers.pc = 0;
     cpu.registers.b = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0x0);
     assert!(cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::N));
     assert!(!cpu.check_flag(ALUFlag::C));
-    assert!(!cpu.check_flag(ALUFlag::H));
+    // carry out of the low nibble
+    assert!(cpu.check_flag(ALUFlag::H));
 }
 
 #[test]
@@ -111,7 +112,7 @@ fn test_dec_r8() {
     // specific opcode for register b
     let mut cpu = setup(0x5);
     cpu.registers.b = 1;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.b, 0x0);
     assert!(cpu.check_flag(ALUFlag::N));
@@ -122,22 +123,23 @@ fn test_dec_r8() {
     // check flags on overflow
     cpu.registers.pc = 0;
     cpu.registers.b = 0x0;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0xFF);
     assert!(cpu.check_flag(ALUFlag::N));
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::C));
-    assert!(!cpu.check_flag(ALUFlag::H));
+    // borrow from the high nibble
+    assert!(cpu.check_flag(ALUFlag::H));
 }
 
 #[test]
 fn test_ld_r8_n8() {
     // specific opcode for register b
     let mut cpu = setup(0x6);
-    cpu.memory.ram[0x1] = 0xFF;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x6);
-    assert_eq!(cpu.memory.ram[0x1], 0xFF);
+    cpu.memory.write_byte(0x1, 0xFF);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x6);
+    assert_eq!(cpu.memory.read_byte(0x1), 0xFF);
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.b, 0xFF);
 }
@@ -146,8 +148,8 @@ fn test_ld_r8_n8() {
 fn test_rlca() {
     // specific opcode for register a
     let mut cpu = setup(0x7);
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x7);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x7);
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0b10);
 }
@@ -157,8 +159,8 @@ fn test_rla() {
     // specific opcode for register a
     let mut cpu = setup(0x17);
     cpu.registers.acc = 0xFF;
-    cpu.exec();
-    assert_eq!(cpu.memory.ram[0x0], 0x17);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.memory.read_byte(0x0), 0x17);
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0b11111110);
     assert_eq!(cpu.registers.flags, ALUFlag::C as u8);
@@ -169,11 +171,11 @@ fn test_ld_a16m_sp() {
     // specific opcode for register sp
     let mut cpu = setup(0x8);
     cpu.registers.sp = 0xAAFF;
-    cpu.exec();
+    assert_eq!(cpu.exec(), Ok(5));
     assert_eq!(cpu.registers.pc, 0x3);
     assert_eq!(cpu.registers.sp, 0xAAFF);
-    assert_eq!(cpu.memory.ram[0x1], 0xAA);
-    assert_eq!(cpu.memory.ram[0x0], 0xFF);
+    assert_eq!(cpu.memory.read_byte(0x1), 0xAA);
+    assert_eq!(cpu.memory.read_byte(0x0), 0xFF);
 }
 
 #[test]
@@ -183,7 +185,7 @@ fn test_add_hl_r16() {
     cpu.registers.high = 0xFF;
     cpu.registers.b = 0x0;
     cpu.registers.c = 0x1;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0xFF);
     assert_eq!(cpu.registers.low, 0x01);
@@ -195,7 +197,7 @@ fn test_add_hl_r16() {
     cpu.registers.low = 0xFF;
     cpu.registers.b = 0x0;
     cpu.registers.c = 0x1;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0x0);
     assert_eq!(cpu.registers.low, 0x0);
@@ -206,19 +208,19 @@ fn test_add_hl_r16() {
 fn test_ld_a_r16m() {
     // opcode where r16 is registers(b,c)
     let mut cpu = setup(0xA);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xAA);
     assert_eq!(cpu.registers.b, 0x2);
     assert_eq!(cpu.registers.c, 0x2);
-    assert_eq!(cpu.memory.ram[0x0202], 0xAA);
+    assert_eq!(cpu.memory.read_byte(0x0202), 0xAA);
 }
 
 #[test]
 fn test_dec_r16() {
     // specific opcode for register b
     let mut cpu = setup(0xB);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.b, 0x2);
     assert_eq!(cpu.registers.c, 0x1);
@@ -229,7 +231,7 @@ fn test_rrca() {
     // specific opcode for register a
     let mut cpu = setup(0xF);
     cpu.registers.acc = 0b100;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0b10);
     assert_eq!(cpu.registers.flags, 0);
@@ -237,7 +239,7 @@ fn test_rrca() {
     // with flags
     cpu.registers.pc = 0;
     cpu.registers.acc = 0x0F;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x87);
     assert!(cpu.check_flag(ALUFlag::C));
@@ -247,10 +249,10 @@ fn test_rrca() {
 fn test_jr_e8() {
     let i: i8 = -5;
     let mut cpu = setup(0x18);
-    cpu.memory.ram[7] = 0x18;
+    cpu.memory.write_byte(7, 0x18);
     cpu.registers.pc = 7;
-    cpu.memory.ram[8] = i as u8;
-    cpu.exec();
+    cpu.memory.write_byte(8, i as u8);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x4);
 }
 
@@ -259,7 +261,7 @@ fn test_rra() {
     // specific opcode for register a
     let mut cpu = setup(0x1F);
     cpu.registers.acc = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0b01111111);
     assert_eq!(cpu.registers.flags, ALUFlag::C as u8);
@@ -267,7 +269,7 @@ fn test_rra() {
     // with carry pre-set to rotate in
     cpu.registers.acc = 0b1111_1110;
     cpu.registers.pc = 0;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.acc, 0xFF);
     assert_eq!(cpu.registers.flags, 0);
 }
@@ -277,64 +279,64 @@ fn test_jr_nz_e8() {
     let i: i8 = -5;
     // specific opcode for register a
     let mut cpu = setup(0x20);
-    cpu.memory.ram[7] = 0x20;
+    cpu.memory.write_byte(7, 0x20);
     cpu.registers.pc = 7;
-    cpu.memory.ram[8] = i as u8;
-    cpu.exec();
+    cpu.memory.write_byte(8, i as u8);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x4);
 
-    cpu.memory.ram[7] = 0x20;
+    cpu.memory.write_byte(7, 0x20);
     cpu.registers.pc = 7;
     cpu.registers.flags = ALUFlag::Z as u8;
-    cpu.memory.ram[8] = i as u8;
-    cpu.exec();
+    cpu.memory.write_byte(8, i as u8);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x9);
 }
 
 #[test]
 fn test_ld_hlim_a() {
     let mut cpu = setup(0x22);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert_eq!(cpu.registers.high, 0x01);
     assert_eq!(cpu.registers.low, 0x01);
-    assert_eq!(cpu.memory.ram[0x0100], 0x1);
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x1);
 }
 
 #[test]
 fn test_ld_a_hlim() {
     let mut cpu = setup(0x2A);
-    cpu.memory.ram[0x0100] = 0xFF;
-    cpu.exec();
+    cpu.memory.write_byte(0x0100, 0xFF);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFF);
     assert_eq!(cpu.registers.high, 0x01);
     assert_eq!(cpu.registers.low, 0x01);
-    assert_eq!(cpu.memory.ram[0x0100], 0xFF);
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xFF);
 }
 
 #[test]
 fn test_ld_a_hldm() {
     let mut cpu = setup(0x3A);
-    cpu.memory.ram[0x0100] = 0xFF;
-    cpu.exec();
+    cpu.memory.write_byte(0x0100, 0xFF);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFF);
     assert_eq!(cpu.registers.high, 0x00);
     assert_eq!(cpu.registers.low, 0xFF);
-    assert_eq!(cpu.memory.ram[0x0100], 0xFF);
+    assert_eq!(cpu.memory.read_byte(0x0100), 0xFF);
 }
 
 #[test]
 fn test_ld_hldm_a() {
     let mut cpu = setup(0x32);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert_eq!(cpu.registers.high, 0x00);
     assert_eq!(cpu.registers.low, 0xFF);
-    assert_eq!(cpu.memory.ram[0x0100], 0x1);
+    assert_eq!(cpu.memory.read_byte(0x0100), 0x1);
 }
 
 #[test]
@@ -346,10 +348,10 @@ fn test_daa() {
     cpu.set_flag(ALUFlag::H, true);
     cpu.registers.acc = 0xFF;
     cpu.registers.pc = 0;
-    cpu.exec();
+    cpu.exec().unwrap();
 
     assert_eq!(cpu.registers.pc, 0x1);
-    assert_eq!(cpu.registers.acc, (0xFF - 0x66));
+    assert_eq!(cpu.registers.acc, 0xFFu8.wrapping_add(0x66));
     assert!(cpu.check_flag(ALUFlag::C));
     assert!(!cpu.check_flag(ALUFlag::H));
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -359,14 +361,13 @@ fn test_daa() {
     cpu.set_flag(ALUFlag::N, true);
     cpu.registers.acc = 0xA0;
     cpu.registers.pc = 0;
-    cpu.exec();
+    cpu.exec().unwrap();
 
-    let mut acc: u8 = 0xA0;
-    acc = acc.wrapping_add(0x60);
+    // after a subtraction only the carry adjusts the high digit
     assert_eq!(cpu.registers.pc, 0x1);
-    assert_eq!(cpu.registers.acc, acc);
+    assert_eq!(cpu.registers.acc, 0xA0 - 0x60);
     assert!(cpu.check_flag(ALUFlag::C));
-    assert!(cpu.check_flag(ALUFlag::Z));
+    assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
     assert!(cpu.check_flag(ALUFlag::N));
 }
@@ -395,17 +396,17 @@ fn test_jr_z_e8() {
     let i: i8 = -5;
     // specific opcode for register a
     let mut cpu = setup(0x28);
-    cpu.memory.ram[7] = 0x28;
+    cpu.memory.write_byte(7, 0x28);
     cpu.registers.pc = 7;
-    cpu.memory.ram[8] = i as u8;
-    cpu.exec();
+    cpu.memory.write_byte(8, i as u8);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x9);
 
-    cpu.memory.ram[7] = 0x28;
+    cpu.memory.write_byte(7, 0x28);
     cpu.registers.pc = 7;
     cpu.registers.flags = ALUFlag::Z as u8;
-    cpu.memory.ram[8] = i as u8;
-    cpu.exec();
+    cpu.memory.write_byte(8, i as u8);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x4);
 }
 
@@ -413,9 +414,9 @@ fn test_jr_z_e8() {
 fn test_ld_sp_n16() {
     // opcode where r16 is registers(b,c)
     let mut cpu = setup(0x31);
-    cpu.memory.ram[0x1] = 0xFF;
-    cpu.memory.ram[0x2] = 0xEE;
-    cpu.exec();
+    cpu.memory.write_byte(0x1, 0xFF);
+    cpu.memory.write_byte(0x2, 0xEE);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x3);
     assert_eq!(cpu.registers.sp, 0xEEFF);
 }
@@ -425,14 +426,14 @@ fn test_inc_r16m() {
     let mut cpu = setup(0x34);
     cpu.registers.high = 0x10;
     cpu.registers.low = 0x10;
-    cpu.memory.ram[0x1010] = 0xFF;
-    cpu.exec();
+    cpu.memory.write_byte(0x1010, 0xFF);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0x10);
     assert_eq!(cpu.registers.low, 0x10);
-    assert_eq!(cpu.memory.ram[0x1010], 0);
+    assert_eq!(cpu.memory.read_byte(0x1010), 0);
     assert!(cpu.check_flag(ALUFlag::Z));
-    assert!(!cpu.check_flag(ALUFlag::H));
+    assert!(cpu.check_flag(ALUFlag::H));
     assert!(!cpu.check_flag(ALUFlag::N));
     assert!(!cpu.check_flag(ALUFlag::C));
 }
@@ -442,12 +443,12 @@ fn test_dec_r16m() {
     let mut cpu = setup(0x35);
     cpu.registers.high = 0x10;
     cpu.registers.low = 0x10;
-    cpu.memory.ram[0x1010] = 0xFF;
-    cpu.exec();
+    cpu.memory.write_byte(0x1010, 0xFF);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0x10);
     assert_eq!(cpu.registers.low, 0x10);
-    assert_eq!(cpu.memory.ram[0x1010], 0xFE);
+    assert_eq!(cpu.memory.read_byte(0x1010), 0xFE);
     assert!(!cpu.check_flag(ALUFlag::Z));
     assert!(!cpu.check_flag(ALUFlag::H));
     assert!(cpu.check_flag(ALUFlag::N));
@@ -459,18 +460,18 @@ fn test_ld_r16m_n8() {
     let mut cpu = setup(0x36);
     cpu.registers.high = 0x10;
     cpu.registers.low = 0x10;
-    cpu.memory.ram[0x1] = 0x66;
-    cpu.exec();
+    cpu.memory.write_byte(0x1, 0x66);
+    assert_eq!(cpu.exec(), Ok(3));
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.high, 0x10);
     assert_eq!(cpu.registers.low, 0x10);
-    assert_eq!(cpu.memory.ram[0x1010], 0x66);
+    assert_eq!(cpu.memory.read_byte(0x1010), 0x66);
 }
 
 #[test]
 fn test_scf() {
     let mut cpu = setup(0x37);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert!(cpu.check_flag(ALUFlag::C));
 }
@@ -478,7 +479,7 @@ fn test_scf() {
 #[test]
 fn test_cpl() {
     let mut cpu = setup(0x2F);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFE);
     assert!(cpu.check_flag(ALUFlag::H));
@@ -492,7 +493,7 @@ fn test_add_r16_sp() {
     cpu.registers.high = 0x00;
     cpu.registers.low = 0xAA;
     cpu.registers.sp = 0xFF00;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0xFF);
     assert_eq!(cpu.registers.low, 0xAA);
@@ -505,11 +506,12 @@ fn test_add_r16_sp() {
     cpu.registers.pc = 0;
     cpu.registers.high = 0xFF;
     cpu.registers.low = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.high, 0xFE);
     assert_eq!(cpu.registers.low, 0xFF);
-    assert!(!cpu.check_flag(ALUFlag::H));
+    // carry out of bit 11
+    assert!(cpu.check_flag(ALUFlag::H));
     assert!(!cpu.check_flag(ALUFlag::N));
     assert!(cpu.check_flag(ALUFlag::C));
 }
@@ -518,7 +520,7 @@ fn test_add_r16_sp() {
 fn test_ld_r8_r8() {
     // specific opcode for registers b,c
     let mut cpu = setup(0x40);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.b, 2);
 }
@@ -527,15 +529,15 @@ fn test_ld_r8_r8() {
 fn test_ld_r16m_r8() {
     // specific opcode for registers b,c
     let mut cpu = setup(0x70);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
-    assert_eq!(cpu.memory.ram[0x0100], cpu.registers.b);
+    assert_eq!(cpu.memory.read_byte(0x0100), cpu.registers.b);
 }
 
 #[test]
 fn test_add_r8_r8() {
     let mut cpu = setup(0x80);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x3);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -549,7 +551,7 @@ fn test_adc_r8_r8() {
     // specific opcode for registers b,c
     let mut cpu = setup(0x88);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x4);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -562,8 +564,8 @@ fn test_adc_r8_r8() {
 fn test_add_r8_r16m() {
     // specific opcode for registers b,c
     let mut cpu = setup(0x86);
-    cpu.memory.ram[0x0100] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x0100, 0xA);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xB);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -575,9 +577,9 @@ fn test_add_r8_r16m() {
 #[test]
 fn test_adc_r8_r16m() {
     let mut cpu = setup(0x8E);
-    cpu.memory.ram[0x0100] = 0xA;
+    cpu.memory.write_byte(0x0100, 0xA);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xC);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -590,7 +592,7 @@ fn test_adc_r8_r16m() {
 fn test_sub_r8_r8() {
     let mut cpu = setup(0x90);
     cpu.registers.acc = 0x3;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -603,9 +605,9 @@ fn test_sub_r8_r8() {
 fn test_sub_r8_r16m() {
     // specific opcode for registers b,c
     let mut cpu = setup(0x96);
-    cpu.memory.ram[0x0100] = 0xA;
+    cpu.memory.write_byte(0x0100, 0xA);
     cpu.registers.acc = 0xB;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -620,7 +622,7 @@ fn test_sbc_r8_r8() {
     let mut cpu = setup(0x98);
     cpu.registers.acc = 0xFF;
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFC);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -633,9 +635,9 @@ fn test_sbc_r8_r8() {
 fn test_sbc_r8_r16m() {
     let mut cpu = setup(0x9E);
     cpu.registers.acc = 0xFF;
-    cpu.memory.ram[0x0100] = 0xFE;
+    cpu.memory.write_byte(0x0100, 0xFE);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x0);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -649,7 +651,7 @@ fn test_and_r8_r8() {
     let mut cpu = setup(0xA0);
     cpu.registers.acc = 0xFF;
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -662,8 +664,8 @@ fn test_and_r8_r8() {
 fn test_and_r8_r16m() {
     let mut cpu = setup(0xA6);
     cpu.registers.acc = 0xFF;
-    cpu.memory.ram[0x0100] = 0xFE;
-    cpu.exec();
+    cpu.memory.write_byte(0x0100, 0xFE);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFE);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -677,7 +679,7 @@ fn test_xor_r8_r8() {
     let mut cpu = setup(0xA8);
     cpu.registers.acc = 0xFF;
     cpu.registers.b = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -690,8 +692,8 @@ fn test_xor_r8_r8() {
 fn test_xor_r8_r16m() {
     let mut cpu = setup(0xAE);
     cpu.registers.acc = 0xFF;
-    cpu.memory.ram[0x0100] = 0xFE;
-    cpu.exec();
+    cpu.memory.write_byte(0x0100, 0xFE);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x1);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -704,7 +706,7 @@ fn test_xor_r8_r16m() {
 fn test_or_r8_r8() {
     let mut cpu = setup(0xB0);
     cpu.registers.acc = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFF);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -717,7 +719,7 @@ fn test_or_r8_r8() {
 fn test_or_r8_r16m() {
     let mut cpu = setup(0xB6);
     cpu.registers.acc = 0xFF;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xFF);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -730,7 +732,7 @@ fn test_or_r8_r16m() {
 fn test_cp_r8_r8() {
     let mut cpu = setup(0xB8);
     cpu.registers.acc = 0x2;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0x2);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -742,9 +744,9 @@ fn test_cp_r8_r8() {
 #[test]
 fn test_cp_r8_r16m() {
     let mut cpu = setup(0xBE);
-    cpu.memory.ram[0x0100] = 0xA;
+    cpu.memory.write_byte(0x0100, 0xA);
     cpu.registers.acc = 0xB;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x1);
     assert_eq!(cpu.registers.acc, 0xB);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -757,8 +759,8 @@ fn test_cp_r8_r16m() {
 fn test_ret_cc() {
     let mut cpu = setup(0xC0);
     cpu.registers.sp = 0x0100;
-    cpu.memory.ram[0x0101] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x0101, 0xA);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.sp, 0x102);
     assert_eq!(cpu.registers.pc, 0xA00);
 }
@@ -767,8 +769,8 @@ fn test_ret_cc() {
 fn test_pop_r16() {
     let mut cpu = setup(0xC1);
     cpu.registers.sp = 0x0100;
-    cpu.memory.ram[0x0101] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x0101, 0xA);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.sp, 0x102);
     assert_eq!(cpu.registers.b, 0xA);
     assert_eq!(cpu.registers.c, 0x0);
@@ -776,44 +778,53 @@ fn test_pop_r16() {
 #[test]
 fn test_push_pop() {
     let mut cpu = setup(0xC5);
-    cpu.memory.ram[0x1] = 0xC1;
-    cpu.memory.ram[0x100] = 0x0;
+    cpu.memory.write_byte(0x1, 0xC1);
+    cpu.memory.write_byte(0x100, 0x0);
     cpu.registers.b = 0xAA;
     cpu.registers.c = 0xFF;
     cpu.registers.sp = 0x0102;
 
     //push
-    cpu.exec();
+    cpu.exec().unwrap();
     // reset registers
     cpu.registers.b = 0;
     cpu.registers.c = 0;
     //pop
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.b, 0xAA);
     assert_eq!(cpu.registers.c, 0xFF);
-    assert_eq!(cpu.memory.ram[0x101], 0xAA);
-    assert_eq!(cpu.memory.ram[0x100], 0xFF);
+    assert_eq!(cpu.memory.read_byte(0x101), 0xAA);
+    assert_eq!(cpu.memory.read_byte(0x100), 0xFF);
 }
 
 #[test]
 fn test_jp_a16_cc() {
     let mut cpu = setup(0xC2);
-    cpu.memory.ram[0x01] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x01, 0xA);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0xA);
 
     cpu.registers.pc = 0;
     cpu.set_flag(ALUFlag::Z, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x03);
 }
 
+#[test]
+fn test_jp_a16() {
+    let mut cpu = setup(0xC3);
+    cpu.memory.write_byte(0x01, 0x34);
+    cpu.memory.write_byte(0x02, 0x12);
+    assert_eq!(cpu.exec(), Ok(4));
+    assert_eq!(cpu.registers.pc, 0x1234);
+}
+
 #[test]
 fn test_adc_r8_n8() {
     let mut cpu = setup(0xCE);
-    cpu.memory.ram[0x01] = 0xFF;
+    cpu.memory.write_byte(0x01, 0xFF);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0x1);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -825,9 +836,9 @@ fn test_adc_r8_n8() {
 #[test]
 fn test_add_r8_n8() {
     let mut cpu = setup(0xC6);
-    cpu.memory.ram[0x01] = 0xA;
+    cpu.memory.write_byte(0x01, 0xA);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0xB);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -841,27 +852,28 @@ fn test_call_a16_cc() {
     // test positive condition
     let mut cpu = setup(0xC4);
     cpu.registers.sp = 0x0102;
-    cpu.memory.ram[0x1] = 0xAA;
-    cpu.memory.ram[0x100] = 0xA;
-    cpu.memory.ram[0x102] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x1, 0xAA);
+    cpu.memory.write_byte(0x100, 0xA);
+    cpu.memory.write_byte(0x102, 0xA);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.sp, 0x100);
     assert_eq!(cpu.registers.pc, 0xAA);
-    assert_eq!(cpu.memory.ram[0x100], 0x03);
-    assert_eq!(cpu.memory.ram[0x101], 0x0);
+    assert_eq!(cpu.memory.read_byte(0x100), 0x03);
+    assert_eq!(cpu.memory.read_byte(0x101), 0x0);
 
     // test false condition
     cpu.registers.sp = 0x0102;
     cpu.registers.pc = 0x0;
     cpu.set_flag(ALUFlag::Z, true);
-    cpu.memory.ram[0x1] = 0xAA;
-    cpu.memory.ram[0x100] = 0xA;
-    cpu.memory.ram[0x102] = 0xA;
-    cpu.exec();
+    cpu.memory.write_byte(0x1, 0xAA);
+    cpu.memory.write_byte(0x100, 0xA);
+    cpu.memory.write_byte(0x102, 0xA);
+    cpu.exec().unwrap();
+    // the address operand is skipped
     assert_eq!(cpu.registers.sp, 0x102);
-    assert_eq!(cpu.registers.pc, 0x1);
-    assert_eq!(cpu.memory.ram[0x100], 0xA);
-    assert_eq!(cpu.memory.ram[0x102], 0xA);
+    assert_eq!(cpu.registers.pc, 0x3);
+    assert_eq!(cpu.memory.read_byte(0x100), 0xA);
+    assert_eq!(cpu.memory.read_byte(0x102), 0xA);
 }
 
 #[test]
@@ -869,10 +881,10 @@ fn test_push_r16() {
     let mut cpu = setup(0xC5);
     cpu.registers.b = 0xAA;
     cpu.registers.sp = 0x0102;
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.sp, 0x100);
-    assert_eq!(cpu.memory.ram[0x100], 0x02);
-    assert_eq!(cpu.memory.ram[0x101], 0xAA);
+    assert_eq!(cpu.memory.read_byte(0x100), 0x02);
+    assert_eq!(cpu.memory.read_byte(0x101), 0xAA);
     assert_eq!(cpu.registers.b, 0xAA);
     assert_eq!(cpu.registers.c, 0x02);
 }
@@ -880,10 +892,10 @@ fn test_push_r16() {
 #[test]
 fn test_sub_r8_n8() {
     let mut cpu = setup(0xD6);
-    cpu.memory.ram[0x01] = 0xA;
+    cpu.memory.write_byte(0x01, 0xA);
     cpu.registers.acc = 0xC;
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0x2);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -895,10 +907,10 @@ fn test_sub_r8_n8() {
 #[test]
 fn test_sbc_r8_n8() {
     let mut cpu = setup(0xDE);
-    cpu.memory.ram[0x01] = 0xA;
+    cpu.memory.write_byte(0x01, 0xA);
     cpu.registers.acc = 0xC;
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0x1);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -911,9 +923,9 @@ fn test_sbc_r8_n8() {
 fn test_and_r8_n8() {
     let mut cpu = setup(0xE6);
     cpu.registers.acc = 0xFF;
-    cpu.memory.ram[0x01] = 0xA;
+    cpu.memory.write_byte(0x01, 0xA);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0xA);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -927,7 +939,7 @@ fn test_or_r8_n8() {
     let mut cpu = setup(0xF6);
     cpu.registers.acc = 0xFF;
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0xFF);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -940,9 +952,9 @@ fn test_or_r8_n8() {
 fn test_xor_r8_n8() {
     let mut cpu = setup(0xEE);
     cpu.registers.acc = 0xF0;
-    cpu.memory.ram[0x01] = 0xFA;
+    cpu.memory.write_byte(0x01, 0xFA);
     cpu.set_flag(ALUFlag::C, true);
-    cpu.exec();
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0xA);
     assert!(!cpu.check_flag(ALUFlag::Z));
@@ -955,8 +967,8 @@ fn test_xor_r8_n8() {
 fn test_cp_r8_n8() {
     let mut cpu = setup(0xFE);
     cpu.registers.acc = 0x2;
-    cpu.memory.ram[0x01] = 0x02;
-    cpu.exec();
+    cpu.memory.write_byte(0x01, 0x02);
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.pc, 0x2);
     assert_eq!(cpu.registers.acc, 0x2);
     assert!(cpu.check_flag(ALUFlag::Z));
@@ -969,10 +981,95 @@ fn test_cp_r8_n8() {
 fn test_reti() {
     let mut cpu = setup(0xD9);
     cpu.registers.sp = 0x0100;
-    cpu.memory.ram[0x0101] = 0xA;
-    cpu.ei = false;
-    cpu.exec();
+    cpu.memory.write_byte(0x0101, 0xA);
+    cpu.ime = false;
+    cpu.exec().unwrap();
     assert_eq!(cpu.registers.sp, 0x102);
     assert_eq!(cpu.registers.pc, 0xA00);
-    assert!(cpu.ei);
+    assert!(cpu.ime);
+}
+
+#[test]
+fn test_interrupt_dispatch() {
+    let mut cpu = setup(0x0);
+    cpu.registers.sp = 0x0104;
+    cpu.ime = true;
+    cpu.memory.write_byte(0xFFFF, 0b0000_0101);
+    cpu.memory.write_byte(0xFF0F, 0b0000_0110);
+    assert_eq!(cpu.exec(), Ok(5));
+    assert_eq!(cpu.registers.pc, 0x50);
+    assert_eq!(cpu.registers.sp, 0x0102);
+    assert_eq!(cpu.memory.read_byte(0x0102), 0x0);
+    assert_eq!(cpu.memory.read_byte(0xFF0F), 0b1110_0010);
+    assert!(!cpu.ime);
+}
+
+#[test]
+fn test_ei_delay() {
+    let mut cpu = setup(0xFB);
+    cpu.registers.sp = 0x0104;
+    cpu.memory.write_byte(0xFFFF, 0b0000_0001);
+    cpu.memory.write_byte(0xFF0F, 0b0000_0001);
+    cpu.exec().unwrap();
+    assert!(!cpu.ime);
+    // the instruction following ei still runs before the interrupt
+    cpu.exec().unwrap();
+    assert_eq!(cpu.registers.pc, 0x2);
+    assert!(cpu.ime);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.registers.pc, 0x40);
+}
+
+#[test]
+fn test_ei_di() {
+    let mut cpu = setup(0xFB);
+    cpu.memory.write_byte(0x1, 0xF3);
+    cpu.memory.write_byte(0xFFFF, 0b0000_0001);
+    cpu.memory.write_byte(0xFF0F, 0b0000_0001);
+    cpu.exec().unwrap();
+    cpu.exec().unwrap();
+    cpu.exec().unwrap();
+    assert!(!cpu.ime);
+    assert_eq!(cpu.registers.pc, 0x3);
+}
+
+#[test]
+fn test_halt_wake_without_ime() {
+    let mut cpu = setup(0x76);
+    cpu.memory.write_byte(0xFFFF, 0b0000_0100);
+    cpu.exec().unwrap();
+    assert!(cpu.halt);
+    assert_eq!(cpu.exec(), Ok(1));
+    assert_eq!(cpu.registers.pc, 0x1);
+    cpu.memory.write_byte(0xFF0F, 0b0000_0100);
+    cpu.exec().unwrap();
+    assert!(!cpu.halt);
+    assert_eq!(cpu.registers.pc, 0x2);
+    // the interrupt is left pending since it wasn't serviced
+    assert_eq!(cpu.memory.read_byte(0xFF0F), 0b1110_0100);
+}
+
+#[test]
+fn test_halt_bug() {
+    // halt; inc a; nop
+    let mut cpu = setup(0x76);
+    cpu.memory.write_byte(0x1, 0x3C);
+    cpu.memory.write_byte(0xFFFF, 0b0000_0001);
+    cpu.memory.write_byte(0xFF0F, 0b0000_0001);
+    cpu.exec().unwrap();
+    assert!(!cpu.halt);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.registers.pc, 0x1);
+    cpu.exec().unwrap();
+    assert_eq!(cpu.registers.pc, 0x2);
+    assert_eq!(cpu.registers.acc, 0x3);
+}
+
+#[test]
+fn test_stop() {
+    // stop is followed by a padding byte which is skipped
+    let mut cpu = setup(0x10);
+    assert_eq!(cpu.exec(), Ok(1));
+    assert_eq!(cpu.registers.pc, 0x2);
+    assert!(cpu.stop);
 }
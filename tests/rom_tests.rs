@@ -1,23 +1,141 @@
-use std::path::Path;
-use they::{BootParameters, system::System};
-
-fn setup(p: Option<&str>) -> System {
-    let p = match p {
-        Some(s) => Path::new(s).to_path_buf(),
-        None => Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("rom_tests/blarggs-test-roms/cpu_instrs/cpu_instrs.gb"),
+use std::path::{Path, PathBuf};
+use they::Hardware::{self, Cgb, Dmg};
+use they::harness::{Harness, Outcome};
+
+fn rom(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("rom_tests/blarggs-test-roms")
+        .join(name)
+}
+
+fn run(name: &str, model: Hardware, frames: u32) -> Outcome {
+    Harness::new(&rom(name), model, frames).run()
+}
+
+fn check(name: &str, model: Hardware, frames: u32) {
+    let outcome = run(name, model, frames);
+    assert!(outcome.passed(), "{name}: {outcome:?}");
+}
+
+/// Every ROM becomes its own test, run on the given model, and is listed in
+/// `ROMS` for the README. ROMs relying on hardware that isn't emulated yet
+/// are ignored.
+macro_rules! rom_tests {
+    ($($test:ident: $name:literal, $model:ident, $frames:literal $(, ignore = $reason:literal)?;)*) => {
+        $(
+            #[test]
+            $(#[ignore = $reason])?
+            fn $test() {
+                check($name, $model, $frames);
+            }
+        )*
+
+        const ROMS: &[(&str, Hardware, u32)] = &[$(($name, $model, $frames)),*];
     };
-    let bp = BootParameters::new(p.to_str());
-    let mut system = System::new(bp);
-    system.initialize();
-    system
 }
 
+rom_tests! {
+    cpu_instrs: "cpu_instrs/cpu_instrs.gb", Dmg, 4000;
+    cpu_instrs_01: "cpu_instrs/individual/01-special.gb", Dmg, 2000;
+    cpu_instrs_02: "cpu_instrs/individual/02-interrupts.gb", Dmg, 2000;
+    cpu_instrs_03: "cpu_instrs/individual/03-op sp,hl.gb", Dmg, 2000;
+    cpu_instrs_04: "cpu_instrs/individual/04-op r,imm.gb", Dmg, 2000;
+    cpu_instrs_05: "cpu_instrs/individual/05-op rp.gb", Dmg, 2000;
+    cpu_instrs_06: "cpu_instrs/individual/06-ld r,r.gb", Dmg, 2000;
+    cpu_instrs_07: "cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", Dmg, 2000;
+    cpu_instrs_08: "cpu_instrs/individual/08-misc instrs.gb", Dmg, 2000;
+    cpu_instrs_09: "cpu_instrs/individual/09-op r,r.gb", Dmg, 2000;
+    cpu_instrs_10: "cpu_instrs/individual/10-bit ops.gb", Dmg, 2000;
+    cpu_instrs_11: "cpu_instrs/individual/11-op a,(hl).gb", Dmg, 2000;
+    instr_timing: "instr_timing/instr_timing.gb", Dmg, 500;
+    mem_timing: "mem_timing/mem_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_01: "mem_timing/individual/01-read_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_02: "mem_timing/individual/02-write_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_03: "mem_timing/individual/03-modify_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_2: "mem_timing-2/mem_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_2_01: "mem_timing-2/rom_singles/01-read_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_2_02: "mem_timing-2/rom_singles/02-write_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    mem_timing_2_03: "mem_timing-2/rom_singles/03-modify_timing.gb", Dmg, 500,
+        ignore = "memory accesses aren't timed within instructions";
+    halt_bug: "halt_bug.gb", Dmg, 500;
+    interrupt_time: "interrupt_time/interrupt_time.gb", Cgb, 500;
+    dmg_sound: "dmg_sound/dmg_sound.gb", Dmg, 4000,
+        ignore = "wave RAM accesses aren't timed within instructions";
+    dmg_sound_01: "dmg_sound/rom_singles/01-registers.gb", Dmg, 1000;
+    dmg_sound_02: "dmg_sound/rom_singles/02-len ctr.gb", Dmg, 1000;
+    dmg_sound_03: "dmg_sound/rom_singles/03-trigger.gb", Dmg, 4000;
+    dmg_sound_04: "dmg_sound/rom_singles/04-sweep.gb", Dmg, 1000;
+    dmg_sound_05: "dmg_sound/rom_singles/05-sweep details.gb", Dmg, 1000;
+    dmg_sound_06: "dmg_sound/rom_singles/06-overflow on trigger.gb", Dmg, 1000;
+    dmg_sound_07: "dmg_sound/rom_singles/07-len sweep period sync.gb", Dmg, 1000;
+    dmg_sound_08: "dmg_sound/rom_singles/08-len ctr during power.gb", Dmg, 1000;
+    dmg_sound_09: "dmg_sound/rom_singles/09-wave read while on.gb", Dmg, 1000,
+        ignore = "wave RAM accesses aren't timed within instructions";
+    dmg_sound_10: "dmg_sound/rom_singles/10-wave trigger while on.gb", Dmg, 1000,
+        ignore = "wave RAM accesses aren't timed within instructions";
+    dmg_sound_11: "dmg_sound/rom_singles/11-regs after power.gb", Dmg, 1000;
+    dmg_sound_12: "dmg_sound/rom_singles/12-wave write while on.gb", Dmg, 1000,
+        ignore = "wave RAM accesses aren't timed within instructions";
+    cgb_sound: "cgb_sound/cgb_sound.gb", Cgb, 4000,
+        ignore = "wave RAM accesses aren't timed within instructions";
+    oam_bug: "oam_bug/oam_bug.gb", Dmg, 4000,
+        ignore = "the OAM corruption bug isn't emulated";
+}
+
+/// Run every ROM, ignored or not, and rewrite the README checklist from the
+/// results:
+///   cargo test --test rom_tests readme_checklist -- --ignored
 #[test]
-fn blarggs_cpu_ld_test() {
-    let p = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("rom_tests/blarggs-test-roms/cpu_instrs/individual/06-ld r,r.gb");
-    let mut system = setup(p.to_str());
-    eprintln!("ram {:#?}", system);
-    //system.run();
+#[ignore = "rewrites README.md"]
+fn readme_checklist() {
+    // ROMs grouped by the directory they're in, in the order of `ROMS`
+    let mut sections: Vec<(&str, Vec<(&str, bool)>)> = Vec::new();
+    for &(name, model, frames) in ROMS {
+        let path = Path::new(name);
+        let section = section_title(path.iter().next().unwrap().to_str().unwrap());
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let result = (stem, run(name, model, frames).passed());
+        match sections.iter_mut().find(|(s, _)| *s == section) {
+            Some((_, roms)) => roms.push(result),
+            None => sections.push((section, vec![result])),
+        }
+    }
+
+    let mut checklist = String::from(
+        "# Blarggs Test Roms\n\n\
+         Generated with `cargo test --test rom_tests readme_checklist -- --ignored`.\n",
+    );
+    for (section, roms) in sections {
+        checklist.push_str(&format!("\n## {section}\n\n"));
+        for (stem, passed) in roms {
+            let mark = if passed { 'x' } else { ' ' };
+            checklist.push_str(&format!("- [{mark}] {stem}\n"));
+        }
+    }
+
+    let readme = Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md");
+    let text = std::fs::read_to_string(&readme).unwrap();
+    let start = text.find("# Blarggs Test Roms").unwrap_or(text.len());
+    std::fs::write(&readme, format!("{}{checklist}", &text[..start])).unwrap();
+}
+
+fn section_title(dir: &str) -> &str {
+    match dir {
+        "cpu_instrs" => "CPU Instructions",
+        "instr_timing" => "Instruction Timing",
+        "mem_timing" => "Memory Timing",
+        "mem_timing-2" => "Memory Timing 2",
+        "interrupt_time" => "Interrupt Timing",
+        "dmg_sound" => "DMG Sound",
+        "cgb_sound" => "CGB Sound",
+        "oam_bug" => "OAM Bug",
+        _ => "Miscellaneous",
+    }
 }